@@ -1,24 +1,35 @@
 use bevy::prelude::*;
 use bevy::sprite::entity::{ SpriteBundle, SpriteSheetBundle };
+use bevy_prototype_lyon::prelude::{ primitive, FillOptions, ShapeType, TessellationMode };
+use bevy_rapier2d::na::Point2;
 use bevy_rapier2d::{
     physics::RigidBodyHandleComponent,
     rapier::{
-        dynamics::{RigidBodyBuilder, RigidBodySet},
+        dynamics::{ RigidBodyBuilder, RigidBodySet },
         geometry::ColliderBuilder,
-        math::Vector,
         //        math::Point,
     },
 };
-use rand::{thread_rng, Rng};
+use rand::rngs::StdRng;
 use rand_distr::Poisson;
 use std::f32;
 use std::fs::File;
+use std::process;
 use super::assets;
+use super::benchmark::Benchmark;
 use super::components::*;
+use super::curriculum::Difficulty;
+use super::explosion::HitFlash;
+use super::geometry::{ Shape, Topology };
+use super::mob;
+use super::novelty::Behaviour;
 use super::player::*;
+use super::scenario::{ BrainSource, Obstacle, Placement };
+use super::simulation;
 use super::state::{ GameState, Mode, RunState, ValidStates };
 
 
+use rand::SeedableRng;
 use rand_distr::Distribution;
 use crate::rapier::WithBody;
 
@@ -33,6 +44,16 @@ pub const WINDOW_WIDTH: u32 = (MARGINS * CAMERA_SCALE * ARENA_WIDTH) as u32;
 pub const WINDOW_HEIGHT: u32 = (MARGINS * CAMERA_SCALE * ARENA_HEIGHT) as u32;
 
 pub const START_LIFE: u32 = 3;
+/// How many shooters get evaluated at the same time in AI mode.
+pub const AI_BORG_COUNT: usize = 4;
+/// How many shooters accompany the player in co-op mode.
+pub const COOP_BORG_COUNT: usize = 2;
+/// Distance from the centre where shooters start.
+const BORG_SPREAD: f32 = 40.0;
+/// Walls are just outside the arena, thick enough that nothing goes through.
+const WALL_THICKNESS: f32 = 20.0;
+/// How many times a random mob position is drawn before giving up on avoiding obstacles.
+const SPAWN_TRIES: usize = 10;
 
 
 pub enum ControlledBy {
@@ -42,54 +63,264 @@ pub enum ControlledBy {
 
 #[derive(Debug)]
 pub struct Arena {
-    /// Kinda reflects how often mobs spawn.
-    pub mob_virility: f32,
+    /// Time since the round started.
+    pub time: f32,
+    /// Index of the next scenario wave to spawn.
+    pub next_wave: usize,
+    /// Stays the same for the whole round.
+    pub difficulty: Difficulty,
+    /// Curriculum level the difficulty comes from
+    pub level: usize,
+    /// Spawn mobs from here instead of the live gene pool.
+    pub opponents: Option<mob::GenePool>,
+    /// Source of spawn positions and mob genotypes.
+    /// Seeded in benchmarks to make rounds repeatable.
+    pub rng: StdRng,
+    /// Walls and the obstacles from the scenario
+    pub obstacles: Vec<Shape>,
+}
+
+impl Arena {
+    /// Genotype for a new mob: from the opponents of the round if there are any,
+    /// otherwise from the live pool.
+    pub fn mob_genotype(&mut self, pool: &mut mob::GenePool) -> mob::Genotype {
+        match &mut self.opponents {
+            Some(opponents) => opponents.spawn_with(&mut self.rng),
+            None => pool.spawn_with(&mut self.rng),
+        }
+    }
+
+    /// Relative position is inside an obstacle.
+    fn blocked(&self, (x, y): (f32, f32)) -> bool {
+        let point = Point2::new(x * ARENA_WIDTH, y * ARENA_HEIGHT);
+        self.obstacles.iter().any(|shape| shape.contains(&point))
+    }
+}
+
+fn walls() -> Vec<Shape> {
+    let half_width = ARENA_WIDTH / 2.0;
+    let half_height = ARENA_HEIGHT / 2.0;
+    let half_thickness = WALL_THICKNESS / 2.0;
+    // Horizontal ones cover the corners.
+    vec![
+        Shape::Rect {
+            x: 0.0,
+            y: half_height + half_thickness,
+            half_width: half_width + WALL_THICKNESS,
+            half_height: half_thickness,
+        },
+        Shape::Rect {
+            x: 0.0,
+            y: -half_height - half_thickness,
+            half_width: half_width + WALL_THICKNESS,
+            half_height: half_thickness,
+        },
+        Shape::Rect {
+            x: -half_width - half_thickness,
+            y: 0.0,
+            half_width: half_thickness,
+            half_height,
+        },
+        Shape::Rect {
+            x: half_width + half_thickness,
+            y: 0.0,
+            half_width: half_thickness,
+            half_height,
+        },
+    ]
+}
+
+/// Spawns just the looks of the obstacle.
+pub fn draw_obstacle<'a>(
+    commands: &'a mut Commands,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    material: Handle<ColorMaterial>,
+    shape: &Shape,
+) -> &'a mut Commands {
+    match shape {
+        Shape::Rect { x, y, half_width, half_height } => commands.spawn(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(half_width * 2.0, half_height * 2.0)),
+            material,
+            transform: Transform::from_translation(Vec3::new(*x, *y, -6.0)),
+            ..Default::default()
+        }),
+        Shape::Circle { x, y, radius } => commands.spawn(primitive(
+            material,
+            meshes,
+            ShapeType::Circle(*radius),
+            TessellationMode::Fill(&FillOptions::default()),
+            Vec3::new(*x, *y, -6.0),
+        )),
+    }
+}
+
+pub fn obstacle_color() -> Color {
+    Color::rgb_u8(0x30, 0x30, 0x48)
+}
+
+fn spawn_obstacle(
+    commands: &mut Commands,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    material: Handle<ColorMaterial>,
+    shape: &Shape,
+) {
+    let collider = match shape {
+        Shape::Rect { half_width, half_height, .. }
+            => ColliderBuilder::cuboid(*half_width, *half_height),
+        Shape::Circle { radius, .. } => ColliderBuilder::ball(*radius),
+    };
+    let centre = shape.centre();
+    draw_obstacle(commands, meshes, material, shape)
+        .with_body(RigidBodyBuilder::new_static().translation(centre.x, centre.y))
+        .with(collider)
+        .with(ValidStates::from_func(GameState::is_arena));
 }
 
 pub fn setup_arena(
     commands: &mut Commands,
     mut runstate: ResMut<RunState>,
     assets: Res<assets::Assets>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut meshes: ResMut<Assets<Mesh>>,
 ) {
     if runstate.gamestate.entering_group_pred(GameState::is_live_arena) {
+        let mode = runstate.gamestate.entering().unwrap().arena_mode().unwrap();
+        let (opponents, rng) = match mode {
+            Mode::Benchmark => {
+                let seed = runstate.benchmark.as_ref().unwrap()
+                    .next_seed()
+                    .expect("Benchmark already over");
+                (Some(Benchmark::opponents()), StdRng::seed_from_u64(seed))
+            },
+            Mode::AI => {
+                let runstate = &mut *runstate;
+                (
+                    runstate.coevolution.start_round(&runstate.mob_gene_pool),
+                    StdRng::from_entropy(),
+                )
+            },
+            // Fleas facing humans come from their own pool,
+            // so the round has nothing to do with coevolution.
+            Mode::Player | Mode::Coop => (None, StdRng::from_entropy()),
+        };
+        // Players and benchmarks get the same arena every time.
+        let (level, difficulty) = match (mode, &runstate.curriculum) {
+            (Mode::AI, Some(curriculum)) => (curriculum.level(), curriculum.difficulty()),
+            _ => (0, Difficulty::default()),
+        };
+        // Nothing to hold in when the edges lead around.
+        let walls = match runstate.topology {
+            Topology::Walled => walls(),
+            Topology::Wrap { .. } => Vec::new(),
+        };
+        let obstacles: Vec<_> = walls.into_iter()
+            .chain(runstate.scenario.obstacles.iter().map(Obstacle::shape))
+            .collect();
+        let material = materials.add(obstacle_color().into());
+        for shape in &obstacles {
+            spawn_obstacle(commands, &mut meshes, material.clone(), shape);
+        }
         runstate.arena = Some(Arena {
-            mob_virility: 0.0,
+            time: 0.0,
+            next_wave: 0,
+            difficulty,
+            level,
+            opponents,
+            rng,
+            obstacles,
         });
-        runstate.score = Some(0);
-        let mode = runstate.gamestate.entering().unwrap().arena_mode().unwrap();
-        let control = match mode {
-            Mode::AI => ControlledBy::AI,
-            Mode::Player => ControlledBy::Player,
+        runstate.fallen_scores.clear();
+        runstate.player_fallen = None;
+        runstate.player = None;
+        match mode {
+            Mode::AI => {
+                for i in 0..AI_BORG_COUNT {
+                    let angle = f32::consts::TAU * i as f32 / AI_BORG_COUNT as f32;
+                    spawn_borg(
+                        commands,
+                        &mut runstate,
+                        &assets,
+                        ControlledBy::AI,
+                        Vec2::new(angle.sin(), angle.cos()) * BORG_SPREAD,
+                    );
+                }
+            },
+            Mode::Player => spawn_borg(
+                commands,
+                &mut runstate,
+                &assets,
+                ControlledBy::Player,
+                Vec2::zero(),
+            ),
+            Mode::Benchmark => spawn_borg(
+                commands,
+                &mut runstate,
+                &assets,
+                ControlledBy::AI,
+                Vec2::zero(),
+            ),
+            Mode::Coop => {
+                spawn_borg(
+                    commands,
+                    &mut runstate,
+                    &assets,
+                    ControlledBy::Player,
+                    Vec2::zero(),
+                );
+                for i in 0..COOP_BORG_COUNT {
+                    let angle = f32::consts::TAU * i as f32 / COOP_BORG_COUNT as f32;
+                    spawn_borg(
+                        commands,
+                        &mut runstate,
+                        &assets,
+                        ControlledBy::AI,
+                        Vec2::new(angle.sin(), angle.cos()) * BORG_SPREAD,
+                    );
+                }
+            },
         };
-        spawn_borg(commands, runstate, assets, control);
     }
 }
 
 fn spawn_borg(
     commands: &mut Commands,
-    mut runstate: ResMut<RunState>,
-    assets: Res<assets::Assets>,
+    runstate: &mut RunState,
+    assets: &assets::Assets,
     control: ControlledBy,
+    position: Vec2,
 ) {
-    let body = RigidBodyBuilder::new_dynamic();
+    let genotype = match control {
+        ControlledBy::Player => None,
+        ControlledBy::AI => Some(match &runstate.benchmark {
+            Some(benchmark) => benchmark.genotype.clone(),
+            None => runstate.shooter_gene_pool.spawn(),
+        }),
+    };
+    let body = genotype.as_ref()
+        .map(|genotype| genotype.body.clone())
+        .unwrap_or_default();
+    let weapon_kind = genotype.as_ref()
+        .map(|genotype| genotype.weapon)
+        .unwrap_or_default();
+
+    let rigid_body = RigidBodyBuilder::new_dynamic()
+        .translation(position.x, position.y);
     let collider = ColliderBuilder::ball(5.0);
 
     commands
         .spawn(SpriteBundle {
             transform: Transform {
-                translation: Vec3::new(0.0, 0.0, -5.0),
+                translation: Vec3::new(position.x, position.y, -5.0),
                 ..Default::default()
             },
             ..Default::default()
         })
-        .with(Borg {
-            rotation_speed: std::f32::consts::TAU,
-            speed: 30.0,
-            life: START_LIFE,
-            time_alive: 0.0,
-            score: 0,
-        })
-        .with_body(body)
+        .with(Borg::new(
+            body.rotation_speed,
+            body.speed,
+            runstate.arena.as_ref().unwrap().difficulty.borg_life,
+        ))
+        .with_body(rigid_body)
         .with(collider)
         .with(ValidStates::from_func(GameState::is_live_arena))
         .with_children(|parent| {
@@ -104,17 +335,24 @@ fn spawn_borg(
             }).with(ValidStates::from_func(GameState::is_live_arena));
         });
 
-    let genotype = runstate.shooter_gene_pool.spawn();
-    println!("Spawned genotype {}", genotype.pretty_print().unwrap());
-    match File::create("shooter.dot")
-        .and_then(|mut f| genotype.to_dot(&mut f))
-    {
-        Err(e) => eprintln!("Filed to write shooter.dot: {:?}", e),
-        Ok(_) => println!("Wrote shooter.dot"),
-    };
-    match control {
-        ControlledBy::Player => commands.with(KeyboardWalk),
-        ControlledBy::AI => commands.with(genotype),
+    match genotype {
+        None => commands.with(KeyboardWalk),
+        Some(genotype) => {
+            println!("Spawned genotype {}", genotype.pretty_print().unwrap());
+            match File::create("shooter.dot")
+                .and_then(|mut f| genotype.brain.to_dot(&mut f))
+            {
+                Err(e) => eprintln!("Filed to write shooter.dot: {:?}", e),
+                Ok(_) => println!("Wrote shooter.dot"),
+            };
+            commands
+                .with(genotype.brain)
+                .with(genotype.body);
+            match runstate.shooter_gene_pool.needs_behaviour() {
+                true => commands.with(Behaviour::default()),
+                false => commands,
+            }
+        },
     };
     
     let borg_entity = commands.current_entity().unwrap();
@@ -122,16 +360,14 @@ fn spawn_borg(
     commands
         .spawn(SpriteBundle {
             transform: Transform {
-                translation: Vec3::new(0.0, 0.0, 0.0),
+                translation: Vec3::new(position.x, position.y, 0.0),
                 scale: Vec3::splat(1.0/8.0),
                 ..Default::default()
             },
             material: assets.borg.clone().unwrap(),
             ..Default::default()
         })
-        .with(Weapon {
-            repeat_timer: Timer::from_seconds(0.5, false),
-        })
+        .with(Weapon::new(weapon_kind, &body))
         .with(AttachedToEntity(borg_entity))
         .with(ValidStates::from_func(GameState::is_live_arena));
 
@@ -153,108 +389,125 @@ pub struct SpawnAsteroidState {
 pub fn spawn_asteroid_system(
     commands: &mut Commands,
     mut local_state: Local<SpawnAsteroidState>,
+    runstate: Res<RunState>,
     assets: Res<assets::Assets>,
     events: Res<Events<AsteroidSpawnEvent>>,
 ) {
+    let speed = runstate.arena.as_ref()
+        .map(|arena| arena.difficulty.mob_speed)
+        .unwrap_or(Difficulty::default().mob_speed);
     for event in local_state.event_reader.iter(&events) {
-        let body = RigidBodyBuilder::new_dynamic()
+        let rigid_body = RigidBodyBuilder::new_dynamic()
             .translation(event.x, event.y);
-        let collider = ColliderBuilder::ball(6.0).friction(-0.3);
+        let body = &event.genotype.body;
+        let radius = event.size.radius() * body.size;
+        let collider = ColliderBuilder::ball(radius).friction(-0.3);
         commands
             .spawn(SpriteSheetBundle {
                 texture_atlas: assets.louse.clone().unwrap(),
                 sprite: TextureAtlasSprite::new(0),
                 transform: {
                     Transform::from_translation(Vec3::new(event.x, event.y, -5.0))
-                        .mul_transform(Transform::from_scale(Vec3::splat(0.5)))
+                        // The sprite is 64px wide, and the small flea is 0.5 of that.
+                        .mul_transform(Transform::from_scale(Vec3::splat(radius / 12.0)))
                 },
                 ..Default::default()
             })
             .with(Mob {
                 size: event.size,
-                life: 1,
-                brain: event.brain.clone(),
-                rotation_speed: f32::consts::TAU / 4.0,
-                speed: 30.0,
+                life: event.size.life() + body.toughness,
+                genotype: event.genotype.clone(),
+                rotation_speed: f32::consts::TAU / 4.0 * body.turn_factor(),
+                speed: speed * event.size.speed_factor() * body.speed_factor(),
             })
-            .with(Damage { value: 1 })
-            .with_body(body)
+            .with(Damage { value: body.damage })
+            .with(HitFlash::new())
+            .with_body(rigid_body)
             .with(collider)
             .with(ValidStates::from_func(GameState::is_arena));
     }
 }
 
 pub fn arena_spawn(
-    time: Res<Time>,
     mut runstate: ResMut<RunState>,
     mut asteroid_spawn_events: ResMut<Events<AsteroidSpawnEvent>>,
 ) {
-    if let GameState::Arena(_) = runstate.gamestate.current() {
-        let mut arena = runstate.arena.as_mut().unwrap();
-        arena.mob_virility += time.delta_seconds();
-        // Mobs per second. Double every 30sec.
-        let spawn_rate = 0.5 * (2.0f32).powf(arena.mob_virility / 30.0);
-        let expected_spawn_this_tick = time.delta_seconds() * spawn_rate;
-        let dist = Poisson::new(expected_spawn_this_tick).unwrap();
-
-        let mut rng = thread_rng();
-        let mobcount: u64 = dist.sample(&mut rng);
-        for _ in 0..mobcount {
-            let x: f32 = rng.gen_range(-0.5, 0.5);
-            let y: f32 = rng.gen_range(-0.5, 0.5);
-            if x.abs() > 0.25 || y.abs() > 0.25 {
-                asteroid_spawn_events.send(AsteroidSpawnEvent {
-                    size: AsteroidSize::Small,
-                    x: x * ARENA_WIDTH,
-                    y: y * ARENA_HEIGHT,
-                    brain: runstate.mob_gene_pool.spawn(),
-                });
+    if let GameState::Arena(mode) = *runstate.gamestate.current() {
+        let runstate = &mut *runstate;
+        let arena = runstate.arena.as_mut().unwrap();
+        let mob_gene_pool = match mode.has_player() {
+            true => &mut runstate.human_mob_gene_pool,
+            false => &mut runstate.mob_gene_pool,
+        };
+        arena.time += simulation::TICK;
+
+        let mut spawn = |arena: &mut Arena, size: AsteroidSize, placement: &Placement, brain: &BrainSource| {
+            let mut position = placement.sample(&mut arena.rng);
+            for _ in 1..SPAWN_TRIES {
+                if !arena.blocked(position) {
+                    break;
+                }
+                position = placement.sample(&mut arena.rng);
+            }
+            let (x, y) = position;
+            asteroid_spawn_events.send(AsteroidSpawnEvent {
+                size,
+                x: x * ARENA_WIDTH,
+                y: y * ARENA_HEIGHT,
+                genotype: match brain {
+                    BrainSource::Pool => arena.mob_genotype(mob_gene_pool),
+                    BrainSource::Random => mob::Genotype::randomize_with(&mut arena.rng),
+                    BrainSource::Fixed(genotype) => genotype.clone(),
+                },
+            });
+        };
+
+        let waves = &runstate.scenario.waves;
+        while let Some(wave) = waves.get(arena.next_wave) {
+            if wave.time > arena.time {
+                break;
+            }
+            for _ in 0..wave.count {
+                spawn(arena, wave.size, &wave.placement, &wave.brain);
+            }
+            arena.next_wave += 1;
+        }
+
+        for stream in &runstate.scenario.streams {
+            let expected_spawn_this_tick = simulation::TICK
+                * stream.rate_at(arena.time)
+                * arena.difficulty.spawn_rate;
+            if expected_spawn_this_tick <= 0.0 {
+                continue;
+            }
+            let dist = Poisson::new(expected_spawn_this_tick).unwrap();
+            let mobcount: u64 = dist.sample(&mut arena.rng);
+            for _ in 0..mobcount {
+                spawn(arena, stream.size, &stream.placement, &stream.brain);
             }
         }
     }
 }
 
-pub fn hold_borgs(
+/// Teleports everything that went past an edge to the opposite one,
+/// keeping its heading and velocity.
+pub fn wrap_around(
     runstate: Res<RunState>,
     mut bodies: ResMut<RigidBodySet>,
-    query: Query<(&RigidBodyHandleComponent, &Borg)>,
+    query: Query<&RigidBodyHandleComponent>,
 ) {
-    if !runstate.gamestate.current().is_live_arena() {
+    if let Topology::Walled = runstate.topology {
         return;
     }
-    for (body_handle, _borg) in query.iter() {
-        let body = bodies.get_mut(body_handle.handle()).unwrap();
-        let mut x = body.position().translation.vector.x;
-        let mut y = body.position().translation.vector.y;
-        let mut xvel = body.linvel().x;
-        let mut yvel = body.linvel().y;
-        let mut updated = false;
-        // Stop at screen edges
-        let half_width = ARENA_WIDTH / 2.0;
-        let half_height = ARENA_HEIGHT / 2.0;
-        if x < -half_width && xvel < 0.0 {
-            x = -half_width;
-            xvel = 0.0;
-            updated = true;
-        } else if x > half_width && xvel > 0.0 {
-            x = half_width;
-            xvel = 0.0;
-            updated = true;
-        }
-        if y < -half_height && yvel < 0.0 {
-            y = -half_height;
-            updated = true;
-            yvel = 0.0;
-        } else if y > half_height && yvel > 0.0 {
-            y = half_height;
-            updated = true;
-            yvel = 0.0;
-        }
-        if updated {
+    for body_handle in query.iter() {
+        let body = match bodies.get_mut(body_handle.handle()) {
+            Some(body) => body,
+            None => continue,
+        };
+        let position = Point2::from(body.position().translation.vector);
+        if let Some(wrapped) = runstate.topology.wrap(&position) {
             let mut new_position = body.position().clone();
-            new_position.translation.vector.x = x;
-            new_position.translation.vector.y = y;
-            body.set_linvel(Vector::new(xvel, yvel), false);
+            new_position.translation.vector = wrapped.coords;
             body.set_position(new_position, false);
         }
     }
@@ -264,15 +517,29 @@ pub fn end_ai_round(
     mut runstate: ResMut<RunState>,
 ) {
     if let GameState::ArenaOver(Mode::AI) = runstate.gamestate.current() {
-        runstate.gamestate.transit_to(GameState::BetweenRounds);
+        runstate.gamestate.transit_to(GameState::BetweenRounds(Mode::AI));
+    }
+}
+
+/// Plays the next scenario, or reports and quits when all are done.
+pub fn end_benchmark_round(
+    mut runstate: ResMut<RunState>,
+) {
+    if let GameState::ArenaOver(Mode::Benchmark) = runstate.gamestate.current() {
+        let benchmark = runstate.benchmark.as_ref().unwrap();
+        if benchmark.is_done() {
+            print!("{}", benchmark.report());
+            process::exit(if benchmark.passed() { 0 } else { 1 });
+        }
+        runstate.gamestate.transit_to(GameState::BetweenRounds(Mode::Benchmark));
     }
 }
 
-pub fn start_ai_round(
+pub fn start_next_round(
     mut runstate: ResMut<RunState>,
 ) {
-    if runstate.gamestate.is(GameState::BetweenRounds) {
-        runstate.gamestate.transit_to(GameState::Arena(Mode::AI));
+    if let GameState::BetweenRounds(mode) = *runstate.gamestate.current() {
+        runstate.gamestate.transit_to(GameState::Arena(mode));
     }
 }
 
@@ -0,0 +1,177 @@
+/*! Repeatable evaluation of a single shooter.
+ * Every scenario has a fixed seed, and the mobs come from a frozen set,
+ * so that results from different days can be compared. */
+
+/*
+ Author: Dorota Czaplejewicz <gihuac.dcz@porcupinefactory.org>
+ SPDX-License-Identifier: AGPL-3.0-or-later
+ */
+
+use rand::rngs::StdRng;
+use std::fmt::Write;
+use std::path::Path;
+use super::fitness::Record;
+use super::mob;
+use super::shooter;
+
+
+use rand::SeedableRng;
+
+
+/// Arena seeds, one per scenario.
+const SEEDS: [u64; 8] = [1, 2, 3, 5, 8, 13, 21, 34];
+/// Seeds for the frozen mob genotypes.
+const MOB_SEEDS: [u64; 4] = [101, 102, 103, 104];
+
+#[derive(Debug)]
+pub struct Benchmark {
+    pub genotype: shooter::Genotype,
+    /// Id in the pool file
+    genotype_id: u64,
+    /// One per round
+    seeds: Vec<u64>,
+    results: Vec<Record>,
+    /// Mean score below this fails the benchmark.
+    min_score: Option<f64>,
+}
+
+impl Benchmark {
+    /// Takes the given genotype from a saved pool, or the fittest one.
+    pub fn load(path: &Path, id: Option<u64>, min_score: Option<f64>)
+        -> Result<Benchmark, anyhow::Error>
+    {
+        let (genotype_id, genotype) = shooter::load_genotype(path, id)?;
+        Ok(Benchmark {
+            genotype,
+            genotype_id,
+            seeds: SEEDS.to_vec(),
+            results: Vec::new(),
+            min_score,
+        })
+    }
+
+    /// The same mobs every time: Eve, and some random ones.
+    pub fn opponents() -> mob::GenePool {
+        mob::GenePool::new_frozen(
+            vec![mob::Genotype::eve()].into_iter()
+                .chain(MOB_SEEDS.iter().map(|seed| {
+                    mob::Genotype::randomize_with(&mut StdRng::seed_from_u64(*seed))
+                }))
+                .collect()
+        )
+    }
+
+    /// Seed for the scenario about to be played, if any are left.
+    pub fn next_seed(&self) -> Option<u64> {
+        self.seeds.get(self.results.len()).cloned()
+    }
+
+    pub fn record(&mut self, record: Record) {
+        self.results.push(record);
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.next_seed().is_none()
+    }
+
+    fn scores(&self) -> Vec<f64> {
+        self.results.iter().map(|r| r.kill_score as f64).collect()
+    }
+
+    fn times(&self) -> Vec<f64> {
+        self.results.iter().map(|r| r.time_alive as f64).collect()
+    }
+
+    pub fn passed(&self) -> bool {
+        match self.min_score {
+            Some(min) => mean_stddev(&self.scores()).0 >= min,
+            None => true,
+        }
+    }
+
+    pub fn report(&self) -> String {
+        let mut f = String::new();
+        let _ = writeln!(f, "Benchmark of genotype {}", self.genotype_id);
+        let _ = writeln!(f, "{:>8} {:>8} {:>10}", "seed", "score", "survival");
+        for (seed, record) in self.seeds.iter().zip(self.results.iter()) {
+            let _ = writeln!(f, "{:>8} {:>8} {:>10.2}", seed, record.kill_score, record.time_alive);
+        }
+        let (score_mean, score_dev) = mean_stddev(&self.scores());
+        let (time_mean, time_dev) = mean_stddev(&self.times());
+        let _ = writeln!(f, "{:>8} {:>8.1} {:>10.2}", "mean", score_mean, time_mean);
+        let _ = writeln!(f, "{:>8} {:>8.1} {:>10.2}", "stddev", score_dev, time_dev);
+        if let Some(min) = self.min_score {
+            let _ = writeln!(
+                f,
+                "{} (minimum score {})",
+                if self.passed() { "PASS" } else { "FAIL" },
+                min,
+            );
+        }
+        f
+    }
+}
+
+/// Population standard deviation.
+fn mean_stddev(values: &[f64]) -> (f64, f64) {
+    if values.is_empty() {
+        return (0.0, 0.0);
+    }
+    let count = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / count;
+    let variance = values.iter()
+        .map(|v| (v - mean) * (v - mean))
+        .sum::<f64>() / count;
+    (mean, variance.sqrt())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::Options;
+    use crate::state::{ GameState, Mode, RunState };
+
+    #[test]
+    fn stats() {
+        assert_eq!(mean_stddev(&[2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]), (5.0, 2.0));
+        assert_eq!(mean_stddev(&[]), (0.0, 0.0));
+    }
+
+    /// The same seed twice in a row, as fast as frames allow,
+    /// must give the same result.
+    #[test]
+    fn rounds_repeat() {
+        // Aims at the nearest flea, so that fleas get hit and split.
+        let mut brain = shooter::Brain::new_dumb(3);
+        brain.hidden_layer[0].weights = vec![1.0, 0.0, 0.0, 0.0];
+        brain.output_layer[0].weights = vec![1.0, 0.0, 0.0, 0.0];
+        let benchmark = Benchmark {
+            genotype: shooter::Genotype {
+                brain,
+                body: Default::default(),
+                weapon: Default::default(),
+            },
+            genotype_id: 0,
+            seeds: vec![3, 3],
+            results: Vec::new(),
+            min_score: None,
+        };
+        let mut runstate = RunState::new(
+            GameState::Arena(Mode::Benchmark),
+            Options { headless: true, ..Default::default() },
+        );
+        runstate.benchmark = Some(benchmark);
+        let mut app = crate::build_app(runstate, Default::default(), true).app;
+        let results = loop {
+            app.update();
+            let runstate = app.resources.get::<RunState>().unwrap();
+            let benchmark = runstate.benchmark.as_ref().unwrap();
+            if benchmark.results.len() == 2 {
+                break benchmark.results.clone();
+            }
+        };
+        assert!(results[0].hits > 0);
+        assert_eq!(results[0], results[1]);
+    }
+}
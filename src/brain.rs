@@ -5,6 +5,18 @@
  SPDX-License-Identifier: AGPL-3.0-or-later
  */
 
+use serde::{ Deserialize, Serialize };
+use std::ops::DerefMut;
+
+
+/// Scratch space for evaluating brains in batches.
+/// Keep it around between batches, so that ticks don't allocate.
+#[derive(Debug, Default)]
+pub struct Buffers {
+    pub inputs: Vec<f32>,
+    pub outputs: Vec<f32>,
+}
+
 /// A generic brain
 pub trait Brain {
     type Inputs;
@@ -12,6 +24,24 @@ pub trait Brain {
     fn process(&mut self, inputs: Self::Inputs) -> Self::Outputs;
     /// Randomly alter itself, according to some abstract strength value
     fn mutate(self, strength: f64) -> Self;
+
+    /// Processes many brains at once, each with its own inputs.
+    /// `outputs` is cleared, and then filled in the order of the batch.
+    fn process_batch<B, I>(
+        batch: I,
+        buffers: &mut Buffers,
+        outputs: &mut Vec<Self::Outputs>,
+    )
+        where Self: Sized,
+            B: DerefMut<Target=Self>,
+            I: IntoIterator<Item=(B, Self::Inputs)>,
+    {
+        let _ = buffers;
+        outputs.clear();
+        outputs.extend(
+            batch.into_iter().map(|(mut brain, inputs)| brain.process(inputs))
+        );
+    }
 }
 
 /// Can mix its genetic code with another.
@@ -20,7 +50,7 @@ pub trait MixableGenotype {
 }
 
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Function {
     Step01,
     StepNegPos,
@@ -59,12 +89,40 @@ impl Function {
             Gaussian => (-(value * value)).exp(),
         }
     }
+
+    /// Slope of the activation at `value`.
+    /// Steps have no useful slope, so they pretend to be linear
+    /// between -1 and 1, and flat outside (straight-through estimate).
+    pub fn derivative(&self, value: f32) -> f32 {
+        use Function::*;
+        match self {
+            Step01 | StepNegPos => match value.abs() <= 1.0 {
+                true => 1.0,
+                false => 0.0,
+            },
+            Linear => 1.0,
+            Logistic => {
+                let y = self.apply(value);
+                y * (1.0 - y)
+            },
+            Tanh => 1.0 - value.tanh().powi(2),
+            ReLU => match value > 0.0 {
+                true => 1.0,
+                false => 0.0,
+            },
+            LReLu => match value > 0.0 {
+                true => 1.0,
+                false => 0.01,
+            },
+            Gaussian => -2.0 * value * (-(value * value)).exp(),
+        }
+    }
 }
 
 
 
 /// Basic neuron. Bias is an input.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Neuron {
     pub weights: Vec<f32>,
     pub activation: Function,
@@ -72,11 +130,14 @@ pub struct Neuron {
 
 impl Neuron {
     pub fn feed(&self, inputs: &[f32]) -> f32 {
-        self.activation.apply(
-            inputs.into_iter()
-                .zip(self.weights.iter())
-                .map(|(i, w)| i * w)
-                .sum()
-        )
+        self.activation.apply(self.sum(inputs))
+    }
+
+    /// Weighted sum of inputs, before activation.
+    pub fn sum(&self, inputs: &[f32]) -> f32 {
+        inputs.into_iter()
+            .zip(self.weights.iter())
+            .map(|(i, w)| i * w)
+            .sum()
     }
 }
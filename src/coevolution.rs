@@ -0,0 +1,204 @@
+/*! Bookkeeping of shooters and fleas evolving against each other.
+ * Scores only mean something when it's known who they were scored against. */
+
+/*
+ Author: Dorota Czaplejewicz <gihuac.dcz@porcupinefactory.org>
+ SPDX-License-Identifier: AGPL-3.0-or-later
+ */
+
+use rand::distributions::Bernoulli;
+use rand::seq::SliceRandom;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use super::mob;
+
+
+use rand::distributions::Distribution;
+
+
+/// Take a snapshot of the mob pool every this many rounds.
+const SNAPSHOT_INTERVAL: u64 = 10;
+/// Print the report every this many rounds.
+const REPORT_INTERVAL: u64 = 50;
+/// Shooters get grouped in the report by this many preserved genotypes.
+const SHOOTER_BIN: u64 = 20;
+
+/// Frozen copy of the mob pool.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    /// Round in which it was taken
+    pub round: u64,
+    pub pool: mob::GenePool,
+}
+
+/// Who the shooters in a round face.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Opponents {
+    /// The live mob pool, which was last snapshotted in the given round.
+    Current { snapshot: u64 },
+    /// A snapshot from the hall of fame.
+    Ancestral { snapshot: u64 },
+}
+
+impl Opponents {
+    fn snapshot(&self) -> u64 {
+        match self {
+            Opponents::Current { snapshot } => *snapshot,
+            Opponents::Ancestral { snapshot } => *snapshot,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Outcome {
+    pub round: u64,
+    /// Id in the shooter gene pool
+    pub shooter: u64,
+    pub opponents: Opponents,
+    pub fitness: f64,
+}
+
+#[derive(Debug)]
+pub struct History {
+    /// Rounds started so far.
+    round: u64,
+    /// The round being fought, numbered like the snapshots.
+    playing: u64,
+    hall_of_fame: Vec<Snapshot>,
+    /// Chance that a round is fought against an ancestral snapshot.
+    ancestral_rate: f64,
+    opponents: Opponents,
+    results: Vec<Outcome>,
+}
+
+impl History {
+    pub fn new() -> History {
+        History {
+            round: 0,
+            playing: 0,
+            hall_of_fame: Vec::new(),
+            ancestral_rate: 0.2,
+            opponents: Opponents::Current { snapshot: 0 },
+            results: Vec::new(),
+        }
+    }
+
+    /// Decides the opponents for the new round.
+    /// Returns a copy of the pool to spawn from if the opponents are ancestral.
+    pub fn start_round(&mut self, current: &mob::GenePool) -> Option<mob::GenePool> {
+        if self.round % SNAPSHOT_INTERVAL == 0 {
+            self.hall_of_fame.push(Snapshot {
+                round: self.round,
+                pool: current.clone(),
+            });
+        }
+        if self.round > 0 && self.round % REPORT_INTERVAL == 0 {
+            println!("{}", self.ciao_report());
+        }
+        self.playing = self.round;
+        self.round += 1;
+
+        let latest = self.hall_of_fame.last().map(|s| s.round).unwrap_or(0);
+        let mut rng = rand::thread_rng();
+        let ancestral = Bernoulli::new(self.ancestral_rate).unwrap().sample(&mut rng);
+        // The latest snapshot is not much different from the current pool.
+        let candidates = &self.hall_of_fame[..self.hall_of_fame.len().saturating_sub(1)];
+        match candidates.choose(&mut rng) {
+            Some(snapshot) if ancestral => {
+                println!("Round {} against mobs from round {}", self.playing, snapshot.round);
+                self.opponents = Opponents::Ancestral { snapshot: snapshot.round };
+                Some(snapshot.pool.clone())
+            },
+            _ => {
+                self.opponents = Opponents::Current { snapshot: latest };
+                None
+            },
+        }
+    }
+
+    pub fn record(&mut self, shooter: u64, fitness: f64) {
+        self.results.push(Outcome {
+            round: self.playing,
+            shooter,
+            opponents: self.opponents,
+            fitness,
+        });
+    }
+
+    /// Current Individual vs. Ancestral Opponents table.
+    /// Rows are groups of shooters by age, columns are mob snapshots,
+    /// cells are mean fitness.
+    /// In an arms race, newer shooters do better against older mobs.
+    /// If they do worse, the populations are cycling.
+    pub fn ciao_report(&self) -> String {
+        let mut cells: BTreeMap<(u64, u64), (f64, u32)> = BTreeMap::new();
+        for result in &self.results {
+            let cell = cells
+                .entry((result.shooter / SHOOTER_BIN, result.opponents.snapshot()))
+                .or_insert((0.0, 0));
+            cell.0 += result.fitness;
+            cell.1 += 1;
+        }
+        let mut columns: Vec<u64> = cells.keys().map(|(_, c)| *c).collect();
+        columns.sort();
+        columns.dedup();
+
+        let mut f = String::new();
+        let _ = writeln!(f, "CIAO: mean fitness of shooters (rows) against mobs from round (columns)");
+        if let (Some(first), Some(last)) = (self.results.first(), self.results.last()) {
+            let _ = writeln!(f, "Rounds {} to {}", first.round, last.round);
+        }
+        let _ = write!(f, "{:>10}", "");
+        for column in &columns {
+            let _ = write!(f, " {:>7}", column);
+        }
+        let _ = writeln!(f);
+        let mut rows: Vec<u64> = cells.keys().map(|(r, _)| *r).collect();
+        rows.dedup();
+        for row in rows {
+            let _ = write!(f, "{:>4}-{:<5}", row * SHOOTER_BIN, (row + 1) * SHOOTER_BIN - 1);
+            for column in &columns {
+                match cells.get(&(row, *column)) {
+                    Some((sum, count)) => { let _ = write!(f, " {:>7.1}", sum / *count as f64); },
+                    None => { let _ = write!(f, " {:>7}", "-"); },
+                }
+            }
+            let _ = writeln!(f);
+        }
+        f
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_cells() {
+        let mut history = History::new();
+        history.opponents = Opponents::Ancestral { snapshot: 10 };
+        history.record(1, 10.0);
+        history.record(2, 20.0);
+        history.opponents = Opponents::Current { snapshot: 20 };
+        history.record(25, 5.0);
+        let report = history.ciao_report();
+        let lines: Vec<_> = report.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert!(lines[3].contains("15.0"));
+        assert!(lines[4].contains("5.0"));
+    }
+
+    #[test]
+    fn outcomes_in_their_round() {
+        let mut history = History::new();
+        let pool = mob::GenePool::new_frozen(Vec::new());
+        history.start_round(&pool);
+        history.record(1, 10.0);
+        history.start_round(&pool);
+        history.record(1, 10.0);
+        let rounds: Vec<_> = history.results.iter().map(|r| r.round).collect();
+        assert_eq!(rounds, vec![0, 1]);
+        assert_eq!(history.results[0].opponents, Opponents::Current { snapshot: 0 });
+    }
+}
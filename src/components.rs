@@ -1,13 +1,17 @@
 use bevy::asset::AssetServer;
 use bevy::audio::Audio;
-use bevy::core::Time;
 use bevy::ecs::{ Commands, Res };
 use bevy::prelude::{ Entity, GlobalTransform, Mut, Quat, Query, Timer, Transform, Without, Vec3 };
 use bevy_rapier2d::na;
 use bevy_rapier2d::na::{ Point2, Rotation2, UnitComplex, Vector2 };
+use serde::Deserialize;
 use super::assets;
+use super::fitness;
 use super::mob;
 use super::laser as projectile;
+use super::shooter;
+use super::simulation;
+use super::weapon;
 
 
 pub struct AttachedToEntity(pub Entity);
@@ -20,8 +24,43 @@ pub struct Borg {
     /// Ship life points
     pub life: u32,
     pub time_alive: f32,
-    /// Better way of estimating success?
+    /// Points for kills made by this borg's lasers.
     pub score: u32,
+    /// Projectiles fired
+    pub shots: u32,
+    /// Projectiles which hit a mob, each counted once
+    pub hits: u32,
+    /// Life points lost
+    pub damage_taken: u32,
+    /// Distance walked
+    pub distance: f32,
+}
+
+impl Borg {
+    pub fn new(rotation_speed: f32, speed: f32, life: u32) -> Borg {
+        Borg {
+            rotation_speed,
+            speed,
+            life,
+            time_alive: 0.0,
+            score: 0,
+            shots: 0,
+            hits: 0,
+            damage_taken: 0,
+            distance: 0.0,
+        }
+    }
+
+    pub fn record(&self) -> fitness::Record {
+        fitness::Record {
+            kill_score: self.score,
+            time_alive: self.time_alive,
+            shots: self.shots,
+            hits: self.hits,
+            damage_taken: self.damage_taken,
+            distance: self.distance,
+        }
+    }
 }
 pub type Ship = Borg;
 
@@ -55,19 +94,55 @@ pub struct AsteroidSpawnEvent {
     pub size: AsteroidSize,
     pub x: f32,
     pub y: f32,
-    pub brain: mob::Brain,
+    pub genotype: mob::Genotype,
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize)]
 pub enum AsteroidSize {
     Big,
     Medium,
     Small,
 }
+
+impl AsteroidSize {
+    /// Collider radius
+    pub fn radius(&self) -> f32 {
+        match self {
+            AsteroidSize::Big => 16.0,
+            AsteroidSize::Medium => 10.0,
+            AsteroidSize::Small => 6.0,
+        }
+    }
+    /// Hits needed to destroy
+    pub fn life(&self) -> u32 {
+        match self {
+            AsteroidSize::Big => 3,
+            AsteroidSize::Medium => 2,
+            AsteroidSize::Small => 1,
+        }
+    }
+    /// Bigger is slower
+    pub fn speed_factor(&self) -> f32 {
+        match self {
+            AsteroidSize::Big => 0.5,
+            AsteroidSize::Medium => 0.75,
+            AsteroidSize::Small => 1.0,
+        }
+    }
+    /// What two pieces it falls apart into when destroyed.
+    pub fn split(&self) -> Option<AsteroidSize> {
+        match self {
+            AsteroidSize::Big => Some(AsteroidSize::Medium),
+            AsteroidSize::Medium => Some(AsteroidSize::Small),
+            AsteroidSize::Small => None,
+        }
+    }
+}
+
 pub struct Mob {
     pub size: AsteroidSize,
     pub life: u32,
-    pub brain: mob::Brain,
+    pub genotype: mob::Genotype,
     /// Max rotation speed in rad/s
     pub rotation_speed: f32,
     /// Max movement speed
@@ -75,41 +150,114 @@ pub struct Mob {
 }
 
 impl Mob {
-    pub fn genotype(&self) -> &mob::Brain {
-        &self.brain
+    pub fn genotype(&self) -> &mob::Genotype {
+        &self.genotype
     }
 }
 
 /// The entity is a weapon, and must have a Transform
 /// because it gives direction to projectiles.
 pub struct Weapon {
+    pub kind: weapon::Kind,
     pub repeat_timer: Timer,
+    /// Multipliers for the stats of the kind, coming from the body
+    pub cooldown_factor: f32,
+    pub speed_factor: f32,
+    pub lifetime_factor: f32,
+}
+
+impl Weapon {
+    pub fn new(kind: weapon::Kind, body: &shooter::Body) -> Weapon {
+        let default = shooter::Body::default();
+        let mut weapon = Weapon {
+            kind,
+            repeat_timer: Timer::from_seconds(0.0, false),
+            cooldown_factor: body.fire_interval / default.fire_interval,
+            speed_factor: body.projectile_speed / default.projectile_speed,
+            lifetime_factor: body.projectile_lifetime / default.projectile_lifetime,
+        };
+        weapon.set_kind(kind);
+        weapon
+    }
+
+    pub fn set_kind(&mut self, kind: weapon::Kind) {
+        self.kind = kind;
+        self.repeat_timer = Timer::from_seconds(
+            kind.stats().cooldown * self.cooldown_factor,
+            false,
+        );
+    }
 }
 
 
-pub fn weapon_repeat(time: Res<Time>, mut weapons: Query<Mut<Weapon>>) {
+pub fn weapon_repeat(mut weapons: Query<Mut<Weapon>>) {
     for mut weapon in &mut weapons.iter_mut() {
-        weapon.repeat_timer.tick(time.delta_seconds());
+        weapon.repeat_timer.tick(simulation::TICK);
     }
 }
 
+/// `owner` is the borg which gets credited for hits.
+/// Returns how many projectiles were fired.
 pub fn weapon_trigger(
     weapon: &mut Weapon,
     transform: &Transform,
+    owner: Entity,
     mut commands: &mut Commands,
     asset_server: &Res<AssetServer>,
     assets: &Res<assets::Assets>,
     audio_output: &Res<Audio>,
-) {
+) -> u32 {
     if weapon.repeat_timer.finished() {
-        projectile::spawn(&mut commands, &asset_server, &assets, &audio_output, transform);
+        projectile::spawn(
+            &mut commands,
+            &asset_server,
+            &assets,
+            &audio_output,
+            transform,
+            owner,
+            weapon,
+        );
         weapon.repeat_timer.reset();
+        weapon.kind.stats().projectiles
+    } else {
+        0
     }
 }
 
 
 pub struct Laser {
     pub despawn_timer: Timer,
+    /// Along its rotation, in units per second
+    pub speed: f32,
+    /// The borg which fired it
+    pub owner: Entity,
+    /// Life points taken from a mob on hit
+    pub damage: u32,
+    /// Survives hits
+    pub piercing: bool,
+    /// Explodes instead of hitting a single mob
+    pub blast_radius: Option<f32>,
+    /// Already counted as a hit for the owner
+    pub hit: bool,
+}
+
+impl Laser {
+    /// True only the first time, so that a projectile going through
+    /// several mobs counts as one hit.
+    pub fn register_hit(&mut self) -> bool {
+        !std::mem::replace(&mut self.hit, true)
+    }
+}
+
+/// An explosion hurting all mobs in the radius.
+#[derive(Debug, Clone)]
+pub struct BlastEvent {
+    pub x: f32,
+    pub y: f32,
+    pub radius: f32,
+    pub damage: u32,
+    /// The borg which gets the points
+    pub owner: Entity,
 }
 pub struct Damage {
     pub value: u32,
@@ -152,3 +300,34 @@ pub fn swivel_at(
         gtransform.rotation = Quat::from_axis_angle(Vec3::new(0.0, 0.0, 1.0), c.angle());
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fitness::{ Accuracy, Fitness };
+
+    #[test]
+    fn projectiles_hit_once() {
+        let mut borg = Borg::new(1.0, 1.0, 3);
+        let stats = weapon::Kind::Beam.stats();
+        let mut beam = Laser {
+            despawn_timer: Timer::from_seconds(stats.lifetime, false),
+            speed: stats.speed,
+            owner: Entity::new(0),
+            damage: stats.damage,
+            piercing: stats.piercing,
+            blast_radius: stats.blast_radius,
+            hit: false,
+        };
+        borg.shots += stats.projectiles;
+        // Goes through three mobs.
+        for _ in 0..3 {
+            if beam.register_hit() {
+                borg.hits += 1;
+            }
+        }
+        assert_eq!(borg.hits, 1);
+        assert_eq!(Accuracy.evaluate(&borg.record()), 1000.0);
+    }
+}
@@ -1,5 +1,6 @@
 use bevy::ecs::QueryError;
 use bevy::prelude::*;
+use bevy_rapier2d::na::Point2;
 use bevy_rapier2d::{
     physics::{EventQueue, RigidBodyHandleComponent},
     rapier::{
@@ -9,26 +10,48 @@ use bevy_rapier2d::{
 };
 use super::components::Borg;
 use super::components::*;
+use super::explosion::HitFlash;
+use super::novelty::Behaviour;
+use super::replay;
 use super::shooter;
 use super::state::*;
 
 
+/// Adds the contact to the replay, if the round is being recorded.
+fn note(runstate: &mut RunState, contact: replay::Contact) {
+    if let Some(replays) = runstate.replays.as_mut() {
+        replays.contact(contact);
+    }
+}
+
 enum Contacts {
     ShipAsteroid(Entity, Entity),
     LaserAsteroid(Entity, Entity),
+    /// Walls and obstacles are the only static bodies.
+    LaserWall(Entity),
+}
+
+#[derive(Default)]
+pub struct ContactState {
+    blast_reader: EventReader<BlastEvent>,
 }
 
 pub fn contact_system(
     commands: &mut Commands,
+    mut state: Local<ContactState>,
+    blast_events: Res<Events<BlastEvent>>,
     mut explosion_spawn_events: ResMut<Events<ExplosionSpawnEvent>>,
+    mut asteroid_spawn_events: ResMut<Events<AsteroidSpawnEvent>>,
     mut runstate: ResMut<RunState>,
     events: Res<EventQueue>,
     bodies: ResMut<RigidBodySet>,
     damages: Query<&Damage>,
-    genotypes: Query<&shooter::Genotype>,
+    genotypes: Query<(&shooter::Brain, &shooter::Body, &Weapon)>,
+    behaviours: Query<&Behaviour>,
     mut ships: Query<Mut<Borg>>,
     mut lasers: Query<Mut<Laser>>,
-    mut mobs: Query<Mut<Mob>>,
+    mut mobs: Query<(Mut<Mob>, Mut<HitFlash>)>,
+    mob_entities: Query<Entity, With<Mob>>,
     handles: Query<&RigidBodyHandleComponent>,
 ) {
     if !runstate.gamestate.current().is_arena() {
@@ -63,38 +86,90 @@ pub fn contact_system(
                 contacts.push(Contacts::LaserAsteroid(e1, e2));
             } else if asteroids.get_mut(e1).is_ok() && lasers.get_mut(e2).is_ok() {
                 contacts.push(Contacts::LaserAsteroid(e2, e1));
+            } else if b2.is_static() && lasers.get_mut(e1).is_ok() {
+                contacts.push(Contacts::LaserWall(e1));
+            } else if b1.is_static() && lasers.get_mut(e2).is_ok() {
+                contacts.push(Contacts::LaserWall(e2));
             }
         }
     }
+    // Lasers which can't hit anything more
+    let mut spent = Vec::new();
+    // Borgs which died, and mobs which rammed a borg.
+    // They stay in queries until commands are applied.
+    let mut dead = Vec::new();
+    let mut rammed = Vec::new();
+    let mut blasts = Vec::new();
+    // Mob, damage, borg to credit
+    let mut hits = Vec::new();
+    // One entry per projectile which hit anything, for accuracy
+    let mut accurate = Vec::new();
     for contact in contacts.into_iter() {
         match contact {
             Contacts::LaserAsteroid(e1, e2) => {
-                let asteroids = &mut mobs;
-                let laser_handle = handles
-                    .get(e1)
+                if spent.contains(&e1) {
+                    // Already used up on another mob this frame.
+                    continue;
+                }
+                let mut laser = lasers.get_mut(e1).unwrap();
+                let laser_position = bodies.get(handles.get(e1).unwrap().handle())
                     .unwrap()
-                    .handle();
-                let asteroid = asteroids.get_mut(e2).unwrap();
-                runstate.score = runstate.score.map(|score| {
-                    score
-                        + match asteroid.size {
-                            AsteroidSize::Small => 40,
-                            AsteroidSize::Medium => 20,
-                            AsteroidSize::Big => 10,
+                    .position()
+                    .translation;
+                match laser.blast_radius {
+                    Some(radius) => blasts.push(BlastEvent {
+                        x: laser_position.x,
+                        y: laser_position.y,
+                        radius,
+                        damage: laser.damage,
+                        owner: laser.owner,
+                    }),
+                    None => {
+                        explosion_spawn_events.send(ExplosionSpawnEvent {
+                            kind: ExplosionKind::LaserOnAsteroid,
+                            x: laser_position.x,
+                            y: laser_position.y,
+                        });
+                        hits.push((e2, laser.damage, laser.owner));
+                        if laser.register_hit() {
+                            accurate.push(laser.owner);
                         }
-                });
-                {
-                    let laser_body = bodies.get(laser_handle).unwrap();
-                    explosion_spawn_events.send(ExplosionSpawnEvent {
-                        kind: ExplosionKind::LaserOnAsteroid,
-                        x: laser_body.position().translation.x,
-                        y: laser_body.position().translation.y,
+                    },
+                }
+                if !laser.piercing {
+                    commands.despawn(e1);
+                    spent.push(e1);
+                    note(&mut runstate, replay::Contact::Spent(e1.to_bits()));
+                }
+            }
+            Contacts::LaserWall(e1) => {
+                if spent.contains(&e1) {
+                    continue;
+                }
+                let laser = lasers.get_mut(e1).unwrap();
+                let laser_position = bodies.get(handles.get(e1).unwrap().handle())
+                    .unwrap()
+                    .position()
+                    .translation;
+                // Grenades go off, even piercing shots stop.
+                if let Some(radius) = laser.blast_radius {
+                    blasts.push(BlastEvent {
+                        x: laser_position.x,
+                        y: laser_position.y,
+                        radius,
+                        damage: laser.damage,
+                        owner: laser.owner,
                     });
                 }
                 commands.despawn(e1);
-                commands.despawn(e2);
+                spent.push(e1);
+                note(&mut runstate, replay::Contact::Spent(e1.to_bits()));
             }
             Contacts::ShipAsteroid(e1, e2) => {
+                if dead.contains(&e1) || rammed.contains(&e2) {
+                    // Already handled this frame.
+                    continue;
+                }
                 let player_body = bodies
                     .get(
                         handles
@@ -106,35 +181,184 @@ pub fn contact_system(
                 let mut borg = ships.get_mut(e1).unwrap();
                 let damage = damages.get(e2).unwrap();
                 borg.life = borg.life.saturating_sub(damage.value);
+                borg.damage_taken += damage.value;
                 if borg.life <= 0 {
+                    note(&mut runstate, replay::Contact::Died(e1.to_bits()));
                     explosion_spawn_events.send(ExplosionSpawnEvent {
                         kind: ExplosionKind::ShipDead,
                         x: player_body.position().translation.x,
                         y: player_body.position().translation.y,
                     });
                     commands.despawn_recursive(e1);
+                    dead.push(e1);
                     // FIXME: despawn LookAts
                     // This is kind of flaky... There could be a separate system to catch brainful despawns.
-                    let score = runstate.score.unwrap_or(0);
-                    match genotypes.get(e1) {
-                        Ok(genotype) => runstate.shooter_gene_pool.preserve(
-                            genotype.clone(),
-                            score as f64,
-                        ),
-                        Err(QueryError::NoSuchEntity) => {},
-                        Err(e) => println!("Borg unuseable genotype {:?}", e),
+                    let score = borg.score;
+                    if runstate.player == Some(e1) {
+                        runstate.player_fallen = Some(runstate.fallen_scores.len());
+                    }
+                    runstate.fallen_scores.push(score);
+                    let record = borg.record();
+                    if let Some(benchmark) = runstate.benchmark.as_mut() {
+                        // Benchmarked genotypes are not bred.
+                        benchmark.record(record);
+                    } else {
+                        let fitness = runstate.fitness.evaluate(&record);
+                        let breakdown = runstate.fitness.breakdown(&record);
+                        match genotypes.get(e1) {
+                            Ok((brain, body, weapon)) => {
+                                let level = runstate.arena.as_ref()
+                                    .map(|arena| arena.level)
+                                    .unwrap_or(0);
+                                let id = runstate.shooter_gene_pool.preserve(
+                                    shooter::Genotype {
+                                        brain: brain.clone(),
+                                        body: body.clone(),
+                                        weapon: weapon.kind,
+                                    },
+                                    record,
+                                    fitness,
+                                    breakdown,
+                                    behaviours.get(e1)
+                                        .map(Behaviour::descriptor)
+                                        .unwrap_or_default(),
+                                    level,
+                                );
+                                // Co-op rounds are not part of the arms race,
+                                // nor fought at the curriculum's difficulty.
+                                if runstate.gamestate.current().arena_mode() == Some(Mode::AI) {
+                                    runstate.coevolution.record(id, fitness);
+                                    if let Some(curriculum) = runstate.curriculum.as_mut() {
+                                        curriculum.record(score);
+                                    }
+                                }
+                                if let Some(path) = &runstate.save_pool {
+                                    if let Err(e) = runstate.shooter_gene_pool.save(path) {
+                                        eprintln!("Failed to save pool to {:?}: {}", path, e);
+                                    }
+                                }
+                            },
+                            Err(QueryError::NoSuchEntity) => {},
+                            Err(e) => println!("Borg unuseable genotype {:?}", e),
+                        }
                     }
                 } else {
+                    note(&mut runstate, replay::Contact::Hurt(e1.to_bits()));
                     explosion_spawn_events.send(ExplosionSpawnEvent {
                         kind: ExplosionKind::ShipContact,
                         x: player_body.position().translation.x,
                         y: player_body.position().translation.y,
                     });
                 }
-                let mob = mobs.get_mut(e2).unwrap();
-                runstate.mob_gene_pool.preserve(mob.genotype().clone());
+                let (mut mob, _flash) = mobs.get_mut(e2).unwrap();
+                // Lasers hitting it this frame are too late.
+                mob.life = 0;
+                rammed.push(e2);
+                // Ancestral mobs are only there for evaluation, not to breed again.
+                let ancestral = runstate.arena.as_ref()
+                    .map(|arena| arena.opponents.is_some())
+                    .unwrap_or(false);
+                if !ancestral {
+                    let runstate = &mut *runstate;
+                    let human = runstate.gamestate.current().arena_mode()
+                        .map(|mode| mode.has_player())
+                        .unwrap_or(false);
+                    let mob_gene_pool = match human {
+                        true => &mut runstate.human_mob_gene_pool,
+                        false => &mut runstate.mob_gene_pool,
+                    };
+                    mob_gene_pool.preserve(mob.genotype().clone());
+                }
                 commands.despawn(e2);
             }
         }
     }
+
+    for blast in state.blast_reader.iter(&blast_events).cloned().chain(blasts) {
+        explosion_spawn_events.send(ExplosionSpawnEvent {
+            kind: ExplosionKind::LaserOnAsteroid,
+            x: blast.x,
+            y: blast.y,
+        });
+        let mut caught = false;
+        for mob in mob_entities.iter() {
+            let position = bodies.get(handles.get(mob).unwrap().handle())
+                .unwrap()
+                .position()
+                .translation;
+            // Blasts reach across the seam too.
+            let offset = runstate.topology.offset(
+                &Point2::new(blast.x, blast.y),
+                &Point2::from(position.vector),
+            );
+            if offset.norm() <= blast.radius {
+                hits.push((mob, blast.damage, blast.owner));
+                caught = true;
+            }
+        }
+        // The grenade was one projectile, however many mobs it got.
+        if caught {
+            accurate.push(blast.owner);
+        }
+    }
+
+    for owner in accurate {
+        if let Ok(mut borg) = ships.get_mut(owner) {
+            borg.hits += 1;
+        }
+    }
+
+    for (e2, damage, owner) in hits {
+        let (mut asteroid, mut flash) = mobs.get_mut(e2).unwrap();
+        if asteroid.life == 0 {
+            // Already destroyed this frame.
+            continue;
+        }
+        asteroid.life = asteroid.life.saturating_sub(damage);
+        let destroyed = asteroid.life == 0;
+        let size = asteroid.size;
+        let points = match size {
+            AsteroidSize::Small => 40,
+            AsteroidSize::Medium => 20,
+            AsteroidSize::Big => 10,
+        };
+        // The shooter may have died while the laser was still flying.
+        if let Ok(mut borg) = ships.get_mut(owner) {
+            if destroyed {
+                borg.score += points;
+            }
+        }
+        if !destroyed {
+            flash.start();
+            note(&mut runstate, replay::Contact::Hit(e2.to_bits()));
+        }
+        if destroyed {
+            note(&mut runstate, replay::Contact::Killed(e2.to_bits()));
+            if let Some(smaller) = size.split() {
+                let position = bodies.get(handles.get(e2).unwrap().handle())
+                    .unwrap()
+                    .position()
+                    .translation;
+                let runstate = &mut *runstate;
+                let arena = runstate.arena.as_mut().unwrap();
+                let human = runstate.gamestate.current().arena_mode()
+                    .map(|mode| mode.has_player())
+                    .unwrap_or(false);
+                let mob_gene_pool = match human {
+                    true => &mut runstate.human_mob_gene_pool,
+                    false => &mut runstate.mob_gene_pool,
+                };
+                // Each piece gets its own genotype.
+                for side in &[-1.0, 1.0] {
+                    asteroid_spawn_events.send(AsteroidSpawnEvent {
+                        size: smaller,
+                        x: position.x + side * smaller.radius(),
+                        y: position.y,
+                        genotype: arena.mob_genotype(mob_gene_pool),
+                    });
+                }
+            }
+            commands.despawn(e2);
+        }
+    }
 }
@@ -0,0 +1,124 @@
+/*! Making the arena harder as shooters get better at it.
+ * Fresh shooters would all die to the same fleas as experienced ones,
+ * and there would be nothing to tell them apart by. */
+
+/*
+ Author: Dorota Czaplejewicz <gihuac.dcz@porcupinefactory.org>
+ SPDX-License-Identifier: AGPL-3.0-or-later
+ */
+
+use std::collections::VecDeque;
+use super::arena::START_LIFE;
+
+
+/// Arena settings for a round.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Difficulty {
+    /// Multiplies the scenario spawn rates
+    pub spawn_rate: f32,
+    /// Max mob movement speed
+    pub mob_speed: f32,
+    pub borg_life: u32,
+}
+
+impl Default for Difficulty {
+    /// No curriculum.
+    fn default() -> Difficulty {
+        Difficulty {
+            spawn_rate: 1.0,
+            mob_speed: 30.0,
+            borg_life: START_LIFE,
+        }
+    }
+}
+
+/// Difficulty and the median score needed to leave it for the next one.
+const LEVELS: [(Difficulty, u32); 5] = [
+    (Difficulty { spawn_rate: 0.5, mob_speed: 20.0, borg_life: 5 }, 160),
+    (Difficulty { spawn_rate: 0.75, mob_speed: 25.0, borg_life: 4 }, 240),
+    (Difficulty { spawn_rate: 1.0, mob_speed: 30.0, borg_life: 3 }, 400),
+    (Difficulty { spawn_rate: 1.5, mob_speed: 35.0, borg_life: 3 }, 640),
+    (Difficulty { spawn_rate: 2.0, mob_speed: 40.0, borg_life: 2 }, u32::MAX),
+];
+
+#[derive(Debug)]
+pub struct Curriculum {
+    level: usize,
+    /// Kill scores of the most recently preserved shooters
+    recent: VecDeque<u32>,
+    /// How many scores decide about changing the level
+    window: usize,
+}
+
+impl Curriculum {
+    pub fn new() -> Curriculum {
+        Curriculum {
+            level: 0,
+            recent: VecDeque::new(),
+            window: 20,
+        }
+    }
+
+    pub fn level(&self) -> usize {
+        self.level
+    }
+
+    pub fn difficulty(&self) -> Difficulty {
+        LEVELS[self.level].0
+    }
+
+    /// Takes the score of a shooter which just died.
+    /// Goes up a level when the median of the recent ones is high enough,
+    /// or down when it's below half of what was needed to get here.
+    /// Returns the new level if it changed.
+    pub fn record(&mut self, score: u32) -> Option<usize> {
+        self.recent.push_back(score);
+        if self.recent.len() > self.window {
+            self.recent.pop_front();
+        }
+        if self.recent.len() < self.window {
+            return None;
+        }
+        let mut sorted: Vec<_> = self.recent.iter().cloned().collect();
+        sorted.sort();
+        let median = sorted[sorted.len() / 2];
+
+        let promote_at = LEVELS[self.level].1;
+        let demote_below = match self.level {
+            0 => 0,
+            level => LEVELS[level - 1].1 / 2,
+        };
+        let new_level = if median >= promote_at {
+            self.level + 1
+        } else if median < demote_below {
+            self.level - 1
+        } else {
+            return None;
+        };
+        println!("Curriculum: median score {}, level {} -> {}", median, self.level, new_level);
+        self.level = new_level;
+        // Scores from the old level don't say anything about the new one.
+        self.recent.clear();
+        Some(new_level)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn promote_and_demote() {
+        let mut curriculum = Curriculum::new();
+        for _ in 0..19 {
+            assert_eq!(curriculum.record(1000), None);
+        }
+        assert_eq!(curriculum.record(1000), Some(1));
+        for _ in 0..19 {
+            assert_eq!(curriculum.record(0), None);
+        }
+        assert_eq!(curriculum.record(0), Some(0));
+        assert_eq!(curriculum.difficulty(), LEVELS[0].0);
+    }
+}
@@ -74,3 +74,40 @@ pub fn handle(
         }
     }
 }
+
+/// Mob was hurt, but survived.
+/// Every mob carries one from the start.
+/// Adding and removing it would move the mob between archetypes
+/// at frame times, changing the order in which ticks see the mobs.
+pub struct HitFlash {
+    timer: Timer,
+}
+
+impl HitFlash {
+    /// Not flashing yet.
+    pub fn new() -> HitFlash {
+        let mut timer = Timer::from_seconds(0.15, false);
+        timer.tick(timer.duration());
+        HitFlash { timer }
+    }
+
+    pub fn start(&mut self) {
+        self.timer.reset();
+    }
+}
+
+pub fn hit_flash(
+    time: Res<Time>,
+    mut query: Query<(Mut<HitFlash>, Mut<TextureAtlasSprite>)>,
+) {
+    for (mut flash, mut sprite) in query.iter_mut() {
+        if flash.timer.finished() {
+            continue;
+        }
+        flash.timer.tick(time.delta_seconds());
+        sprite.color = match flash.timer.finished() {
+            true => Color::WHITE,
+            false => Color::rgb(1.0, 0.3, 0.3),
+        };
+    }
+}
@@ -0,0 +1,160 @@
+/*! Ways to judge shooters */
+
+/*
+ Author: Dorota Czaplejewicz <gihuac.dcz@porcupinefactory.org>
+ SPDX-License-Identifier: AGPL-3.0-or-later
+ */
+
+use std::fmt;
+
+
+/// What a borg achieved during its life.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Record {
+    /// Points for kills
+    pub kill_score: u32,
+    /// Seconds
+    pub time_alive: f32,
+    pub shots: u32,
+    pub hits: u32,
+    /// Life points lost to mobs
+    pub damage_taken: u32,
+    /// Distance walked, in pixels
+    pub distance: f32,
+}
+
+/// Named parts of a fitness value.
+pub type Breakdown = Vec<(&'static str, f64)>;
+
+/// Turns a record into a single number.
+/// Results should stay roughly on the scale of kill points,
+/// because gene pools add constants to them when weighting.
+pub trait Fitness: fmt::Debug + Send + Sync {
+    fn evaluate(&self, record: &Record) -> f64;
+    /// The parts that went into the result.
+    fn breakdown(&self, record: &Record) -> Breakdown;
+}
+
+#[derive(Debug)]
+pub struct KillScore;
+
+impl Fitness for KillScore {
+    fn evaluate(&self, record: &Record) -> f64 {
+        record.kill_score as f64
+    }
+    fn breakdown(&self, record: &Record) -> Breakdown {
+        vec![("kills", self.evaluate(record))]
+    }
+}
+
+/// 10 points per second.
+#[derive(Debug)]
+pub struct SurvivalTime;
+
+impl Fitness for SurvivalTime {
+    fn evaluate(&self, record: &Record) -> f64 {
+        record.time_alive as f64 * 10.0
+    }
+    fn breakdown(&self, record: &Record) -> Breakdown {
+        vec![("survival", self.evaluate(record))]
+    }
+}
+
+/// Hits per shot, in permille. Not shooting at all is worth nothing.
+#[derive(Debug)]
+pub struct Accuracy;
+
+impl Fitness for Accuracy {
+    fn evaluate(&self, record: &Record) -> f64 {
+        match record.shots {
+            0 => 0.0,
+            shots => 1000.0 * record.hits as f64 / shots as f64,
+        }
+    }
+    fn breakdown(&self, record: &Record) -> Breakdown {
+        vec![("accuracy", self.evaluate(record))]
+    }
+}
+
+/// Seconds survived per life point lost, 10 points per second.
+#[derive(Debug)]
+pub struct DamageAvoided;
+
+impl Fitness for DamageAvoided {
+    fn evaluate(&self, record: &Record) -> f64 {
+        10.0 * record.time_alive as f64 / record.damage_taken.max(1) as f64
+    }
+    fn breakdown(&self, record: &Record) -> Breakdown {
+        vec![("avoidance", self.evaluate(record))]
+    }
+}
+
+/// Sum of other fitness functions, each with its own weight.
+#[derive(Debug)]
+pub struct Weighted(pub Vec<(f64, Box<dyn Fitness>)>);
+
+impl Fitness for Weighted {
+    fn evaluate(&self, record: &Record) -> f64 {
+        self.0.iter()
+            .map(|(weight, fitness)| weight * fitness.evaluate(record))
+            .sum()
+    }
+    fn breakdown(&self, record: &Record) -> Breakdown {
+        self.0.iter()
+            .flat_map(|(weight, fitness)| {
+                fitness.breakdown(record).into_iter()
+                    .map(move |(name, value)| (name, weight * value))
+            })
+            .collect()
+    }
+}
+
+/// Finds a built-in fitness function by name.
+pub fn from_name(name: &str) -> Option<Box<dyn Fitness>> {
+    let fitness: Box<dyn Fitness> = match name {
+        "kills" => Box::new(KillScore),
+        "survival" => Box::new(SurvivalTime),
+        "accuracy" => Box::new(Accuracy),
+        "avoidance" => Box::new(DamageAvoided),
+        "mixed" => Box::new(Weighted(vec![
+            (1.0, Box::new(KillScore) as Box<dyn Fitness>),
+            (0.5, Box::new(SurvivalTime) as Box<dyn Fitness>),
+            (0.2, Box::new(Accuracy) as Box<dyn Fitness>),
+        ])),
+        _ => return None,
+    };
+    Some(fitness)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_shots() {
+        assert_eq!(Accuracy.evaluate(&Record::default()), 0.0);
+    }
+
+    #[test]
+    fn weighted_breakdown() {
+        let fitness = from_name("mixed").unwrap();
+        let record = Record {
+            kill_score: 40,
+            time_alive: 2.0,
+            shots: 4,
+            hits: 1,
+            damage_taken: 3,
+            distance: 0.0,
+        };
+        let breakdown = fitness.breakdown(&record);
+        assert_eq!(
+            breakdown,
+            vec![("kills", 40.0), ("survival", 10.0), ("accuracy", 50.0)],
+        );
+        assert_eq!(
+            breakdown.iter().map(|(_, v)| v).sum::<f64>(),
+            fitness.evaluate(&record),
+        );
+    }
+}
@@ -0,0 +1,79 @@
+/*! Gamepad bookkeeping.
+ * Any connected gamepad controls the player. */
+
+/*
+ Author: Dorota Czaplejewicz <gihuac.dcz@porcupinefactory.org>
+ SPDX-License-Identifier: AGPL-3.0-or-later
+ */
+
+use bevy::app::{ EventReader, Events };
+use bevy::ecs::{ Local, Res, ResMut };
+use bevy::input::{ Axis, Input };
+use bevy::input::gamepad::{
+    Gamepad, GamepadAxis, GamepadAxisType, GamepadButton, GamepadButtonType,
+    GamepadEvent, GamepadEventType,
+};
+use bevy::math::Vec2;
+use std::collections::HashSet;
+
+
+/// Sticks closer to the centre than this are at rest.
+const DEAD_ZONE: f32 = 0.2;
+
+#[derive(Debug, Default)]
+pub struct Lobby {
+    gamepads: HashSet<Gamepad>,
+}
+
+impl Lobby {
+    /// Any of the gamepads has the button down.
+    pub fn pressed(&self, buttons: &Input<GamepadButton>, kind: GamepadButtonType) -> bool {
+        self.gamepads.iter()
+            .any(|gamepad| buttons.pressed(GamepadButton(*gamepad, kind)))
+    }
+
+    pub fn just_pressed(&self, buttons: &Input<GamepadButton>, kind: GamepadButtonType) -> bool {
+        self.gamepads.iter()
+            .any(|gamepad| buttons.just_pressed(GamepadButton(*gamepad, kind)))
+    }
+
+    /// Position of the first stick out of the dead zone, up to 1 long.
+    pub fn stick(
+        &self,
+        axes: &Axis<GamepadAxis>,
+        x: GamepadAxisType,
+        y: GamepadAxisType,
+    ) -> Option<Vec2> {
+        self.gamepads.iter()
+            .filter_map(|gamepad| {
+                let x = axes.get(GamepadAxis(*gamepad, x))?;
+                let y = axes.get(GamepadAxis(*gamepad, y))?;
+                Some(Vec2::new(x, y))
+            })
+            .find(|v| v.length() > DEAD_ZONE)
+            .map(|v| match v.length() > 1.0 {
+                true => v.normalize(),
+                false => v,
+            })
+    }
+}
+
+pub fn connection(
+    mut lobby: ResMut<Lobby>,
+    mut reader: Local<EventReader<GamepadEvent>>,
+    events: Res<Events<GamepadEvent>>,
+) {
+    for event in reader.iter(&events) {
+        match event {
+            GamepadEvent(gamepad, GamepadEventType::Connected) => {
+                println!("Gamepad {:?} connected", gamepad);
+                lobby.gamepads.insert(*gamepad);
+            },
+            GamepadEvent(gamepad, GamepadEventType::Disconnected) => {
+                println!("Gamepad {:?} disconnected", gamepad);
+                lobby.gamepads.remove(gamepad);
+            },
+            _ => {},
+        }
+    }
+}
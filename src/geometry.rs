@@ -7,22 +7,79 @@
 use bevy::math::{ Quat, Vec3 };
 use bevy_rapier2d::na::{ Point2, Rotation2, UnitComplex, Vector2 };
 use bevy_rapier2d::rapier::math::{ Isometry, Translation, Vector };
+use serde::{ Deserialize, Serialize };
 use std::cmp::Ordering::Equal;
 
 
-pub fn angle_from(position: &Isometry<f32>, target: &Point2<f32>) -> f32 {
-    let point: Point2<f32> = position.inverse_transform_point(target);
+/// How the edges of the arena connect.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum Topology {
+    /// Walls keep everything inside.
+    #[default]
+    Walled,
+    /// Whatever leaves through an edge comes back through the opposite one.
+    /// The arena is centred on the origin.
+    Wrap { width: f32, height: f32 },
+}
+
+/// Shortest signed distance along one axis of a loop.
+fn wrap_axis(value: f32, size: f32) -> f32 {
+    value - size * (value / size).round()
+}
+
+impl Topology {
+    /// Shortest way from one point to the other, across the seam if that's closer.
+    pub fn offset(&self, from: &Point2<f32>, to: &Point2<f32>) -> Vector2<f32> {
+        let offset = to - from;
+        match self {
+            Topology::Walled => offset,
+            Topology::Wrap { width, height } => Vector2::new(
+                wrap_axis(offset.x, *width),
+                wrap_axis(offset.y, *height),
+            ),
+        }
+    }
+
+    /// Where a point which went past an edge comes back in.
+    /// None if it's still inside.
+    pub fn wrap(&self, point: &Point2<f32>) -> Option<Point2<f32>> {
+        match self {
+            Topology::Walled => None,
+            Topology::Wrap { width, height } => {
+                match point.x.abs() > width / 2.0 || point.y.abs() > height / 2.0 {
+                    true => Some(Point2::new(
+                        wrap_axis(point.x, *width),
+                        wrap_axis(point.y, *height),
+                    )),
+                    false => None,
+                }
+            },
+        }
+    }
+}
+
+pub fn angle_from(position: &Isometry<f32>, target: &Point2<f32>, topology: Topology) -> f32 {
+    let origin = Point2::from(position.translation.vector);
+    let target = origin + topology.offset(&origin, target);
+    let point: Point2<f32> = position.inverse_transform_point(&target);
     Rotation2::rotation_between(
         &Vector2::new(0.0, 1.0),
         &Vector2::new(point.x, point.y),
     ).angle()
 }
 
-pub fn get_nearest(position: &Point2<f32>, others: &[Point2<f32>]) -> Option<Point2<f32>> {
+/// The result is as seen from `position`,
+/// so it may lie outside the arena when the nearest one is across the seam.
+pub fn get_nearest(
+    position: &Point2<f32>,
+    others: &[Point2<f32>],
+    topology: Topology,
+) -> Option<Point2<f32>> {
     others.iter()
-        .map(|p| (p, (p - position).norm()))
+        .map(|p| topology.offset(position, p))
+        .map(|offset| (offset, offset.norm()))
         .min_by(|(_, norm), (_, norm2)| norm.partial_cmp(norm2).unwrap_or(Equal))
-        .map(|(p, _norm)| p.clone())
+        .map(|(offset, _norm)| position + offset)
 }
 
 pub fn into_isometry_2d(translation: Vec3, rotation: Quat) -> Isometry<f32> {
@@ -37,3 +94,124 @@ pub fn into_isometry_2d(translation: Vec3, rotation: Quat) -> Isometry<f32> {
         UnitComplex::new(angle),
     )
 }
+
+/// Something solid in the arena, in arena coordinates.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Shape {
+    Rect { x: f32, y: f32, half_width: f32, half_height: f32 },
+    Circle { x: f32, y: f32, radius: f32 },
+}
+
+impl Shape {
+    pub fn centre(&self) -> Point2<f32> {
+        match self {
+            Shape::Rect { x, y, .. } | Shape::Circle { x, y, .. } => Point2::new(*x, *y),
+        }
+    }
+
+    pub fn contains(&self, point: &Point2<f32>) -> bool {
+        let offset = point - self.centre();
+        match self {
+            Shape::Rect { half_width, half_height, .. }
+                => offset.x.abs() < *half_width && offset.y.abs() < *half_height,
+            Shape::Circle { radius, .. } => offset.norm() < *radius,
+        }
+    }
+
+    /// How far along the ray the surface is, if it's in the way.
+    /// `direction` must be normalized. Zero from the inside.
+    pub fn ray_distance(&self, origin: &Point2<f32>, direction: &Vector2<f32>) -> Option<f32> {
+        if self.contains(origin) {
+            return Some(0.0);
+        }
+        let offset = origin - self.centre();
+        match self {
+            Shape::Rect { half_width, half_height, .. } => {
+                // Slabs: the ray is inside the rectangle
+                // where it's between both pairs of sides at the same time.
+                let mut near = f32::NEG_INFINITY;
+                let mut far = f32::INFINITY;
+                for &(o, d, half) in &[
+                    (offset.x, direction.x, *half_width),
+                    (offset.y, direction.y, *half_height),
+                ] {
+                    if d.abs() < f32::EPSILON {
+                        if o.abs() > half {
+                            return None;
+                        }
+                    } else {
+                        let t0 = (-half - o) / d;
+                        let t1 = (half - o) / d;
+                        near = near.max(t0.min(t1));
+                        far = far.min(t0.max(t1));
+                    }
+                }
+                match near <= far && near >= 0.0 {
+                    true => Some(near),
+                    false => None,
+                }
+            },
+            Shape::Circle { radius, .. } => {
+                let along = offset.dot(direction);
+                let discriminant = along * along - (offset.norm_squared() - radius * radius);
+                if discriminant < 0.0 {
+                    return None;
+                }
+                let t = -along - discriminant.sqrt();
+                match t >= 0.0 {
+                    true => Some(t),
+                    false => None,
+                }
+            },
+        }
+    }
+}
+
+/// Distance to the closest shape along the ray.
+pub fn ray_distance(shapes: &[Shape], origin: &Point2<f32>, direction: &Vector2<f32>) -> Option<f32> {
+    shapes.iter()
+        .filter_map(|shape| shape.ray_distance(origin, direction))
+        .min_by(|a, b| a.partial_cmp(b).unwrap_or(Equal))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32;
+
+    #[test]
+    fn rays_hit() {
+        let rect = Shape::Rect { x: 10.0, y: 0.0, half_width: 2.0, half_height: 5.0 };
+        let circle = Shape::Circle { x: 0.0, y: 10.0, radius: 3.0 };
+        let origin = Point2::new(0.0, 0.0);
+        let d = rect.ray_distance(&origin, &Vector2::x()).unwrap();
+        assert!((d - 8.0).abs() < 0.001);
+        let d = circle.ray_distance(&origin, &Vector2::y()).unwrap();
+        assert!((d - 7.0).abs() < 0.001);
+        assert_eq!(rect.ray_distance(&origin, &-Vector2::x()), None);
+        assert_eq!(circle.ray_distance(&origin, &Vector2::x()), None);
+        assert_eq!(rect.ray_distance(&Point2::new(10.0, 4.0), &Vector2::y()), Some(0.0));
+        let d = ray_distance(&[rect, circle], &origin, &Vector2::new(1.0, 1.0).normalize());
+        assert_eq!(d, None);
+    }
+
+    #[test]
+    fn nearest_across_seam() {
+        let torus = Topology::Wrap { width: 100.0, height: 100.0 };
+        let position = Point2::new(45.0, 0.0);
+        let others = [Point2::new(-45.0, 0.0), Point2::new(20.0, 0.0)];
+        assert_eq!(get_nearest(&position, &others, Topology::Walled), Some(others[1]));
+        let nearest = get_nearest(&position, &others, torus).unwrap();
+        assert!((nearest - Point2::new(55.0, 0.0)).norm() < 0.001);
+        // Facing up, the one across the right edge is to the right.
+        let isometry = Isometry::translation(45.0, 0.0);
+        let angle = angle_from(&isometry, &others[0], torus);
+        assert!((angle + f32::consts::FRAC_PI_2).abs() < 0.001);
+        let angle = angle_from(&isometry, &others[0], Topology::Walled);
+        assert!((angle.abs() - f32::consts::FRAC_PI_2).abs() < 0.001);
+        assert_eq!(torus.wrap(&Point2::new(10.0, -20.0)), None);
+        let wrapped = torus.wrap(&Point2::new(51.0, -20.0)).unwrap();
+        assert!((wrapped - Point2::new(-49.0, -20.0)).norm() < 0.001);
+    }
+}
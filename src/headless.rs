@@ -0,0 +1,67 @@
+/*! Running without a window or sound, for benchmarks on machines without a screen. */
+
+/*
+ Author: Dorota Czaplejewicz <gihuac.dcz@porcupinefactory.org>
+ SPDX-License-Identifier: AGPL-3.0-or-later
+ */
+
+use bevy::app;
+use bevy::app::{ PluginGroupBuilder, ScheduleRunnerPlugin, ScheduleRunnerSettings };
+use bevy::asset::AddAsset;
+use bevy::audio::{ Audio, AudioPlugin, AudioSource, Mp3Loader };
+use bevy::ecs::{ IntoSystem, Res };
+use bevy::gltf::GltfPlugin;
+use bevy::pbr::PbrPlugin;
+use bevy::render::RenderPlugin;
+use bevy::render::mesh::Mesh;
+use bevy::render::texture::{ ImageTextureLoader, Texture };
+use bevy::sprite::{ ColorMaterial, SpritePlugin, TextureAtlas };
+use bevy::text::{ Font, FontLoader, TextPlugin };
+use bevy::ui::UiPlugin;
+use bevy::wgpu::WgpuPlugin;
+use bevy::winit::WinitPlugin;
+use std::time::Duration;
+
+
+/// Leaves out of the default plugins the ones which draw or play.
+pub fn default_plugins(group: &mut PluginGroupBuilder) -> &mut PluginGroupBuilder {
+    group.disable::<RenderPlugin>()
+        .disable::<SpritePlugin>()
+        .disable::<PbrPlugin>()
+        .disable::<UiPlugin>()
+        .disable::<TextPlugin>()
+        .disable::<AudioPlugin>()
+        .disable::<GltfPlugin>()
+        .disable::<WgpuPlugin>()
+        .disable::<WinitPlugin>()
+}
+
+/// Stands in for what `default_plugins` left out.
+/// The game still creates meshes, materials and text,
+/// so their assets must exist, even if nothing draws them.
+pub struct Plugin;
+
+impl app::Plugin for Plugin {
+    fn build(&self, app: &mut app::AppBuilder) {
+        app.add_asset::<Mesh>()
+            .add_asset::<Texture>()
+            .add_asset::<TextureAtlas>()
+            .add_asset::<ColorMaterial>()
+            .add_asset::<Font>()
+            .init_asset_loader::<ImageTextureLoader>()
+            .init_asset_loader::<FontLoader>()
+            // Sounds still get loaded once, so that they don't get loaded on every shot.
+            .add_asset::<AudioSource>()
+            .init_asset_loader::<Mp3Loader>()
+            .init_resource::<Audio>()
+            .add_system(drop_sounds.system())
+            // Frames come one after another, without waiting for the screen.
+            .add_resource(ScheduleRunnerSettings::run_loop(Duration::from_secs(0)))
+            .add_plugin(ScheduleRunnerPlugin {});
+    }
+}
+
+/// Nothing plays queued sounds.
+fn drop_sounds(audio: Res<Audio>) {
+    audio.queue.write().clear();
+}
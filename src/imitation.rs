@@ -0,0 +1,318 @@
+/*! Learning from humans.
+ * Player rounds can be recorded as what the player saw and what they did about it,
+ * in the same terms as the shooter brain uses.
+ * A brain can then be fitted to behave like the recorded player. */
+
+/*
+ Author: Dorota Czaplejewicz <gihuac.dcz@porcupinefactory.org>
+ SPDX-License-Identifier: AGPL-3.0-or-later
+ */
+
+use bevy::ecs::{ Query, Res, ResMut, With };
+use bevy::transform::components::Transform;
+use bevy_rapier2d::na::{ Point2, Vector2 };
+use bevy_rapier2d::{
+    physics::RigidBodyHandleComponent,
+    rapier::dynamics::RigidBodySet,
+};
+use serde::{ Deserialize, Serialize };
+use std::f32;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::path::{ Path, PathBuf };
+use super::brain;
+use super::components::{ AttachedToEntity, Borg, LooksAt, Mob, Weapon };
+use super::geometry::into_isometry_2d;
+use super::input_map::{ Action, Actions };
+use super::shooter;
+use super::state::{ GameState, Mode, RunState };
+
+
+use super::brain::Brain as _;
+
+
+/// What the player did during one tick.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Sample {
+    /// Normalized like the shooter brain inputs
+    pub inputs: Vec<f32>,
+    /// Forward speed relative to the max, -1..1
+    pub walk: f32,
+    /// Rotation speed relative to the max, -1..1
+    pub turn: f32,
+    /// Aim relative to the walking direction, in half turns, -1..1
+    pub aim: f32,
+    pub shoot: bool,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Dataset {
+    pub samples: Vec<Sample>,
+}
+
+impl Dataset {
+    /// A missing file is an empty dataset.
+    /// Samples recorded before an input got added see nothing there.
+    pub fn load(path: &Path) -> Result<Dataset, anyhow::Error> {
+        let mut dataset: Dataset = match File::open(path) {
+            Ok(f) => ron::de::from_reader(f)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Dataset::default(),
+            Err(e) => return Err(e.into()),
+        };
+        for sample in dataset.samples.iter_mut() {
+            sample.inputs.resize(shooter::INPUT_COUNT as usize, 0.0);
+        }
+        Ok(dataset)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), anyhow::Error> {
+        fs::write(path, ron::ser::to_string(self)?)?;
+        Ok(())
+    }
+}
+
+/// Samples get added to what was recorded previously.
+#[derive(Debug)]
+pub struct Recorder {
+    pub path: PathBuf,
+    pub dataset: Dataset,
+}
+
+impl Recorder {
+    pub fn load(path: PathBuf) -> Result<Recorder, anyhow::Error> {
+        Ok(Recorder {
+            dataset: Dataset::load(&path)?,
+            path,
+        })
+    }
+}
+
+/// Wraps the angle into -PI..PI.
+fn wrap_angle(angle: f32) -> f32 {
+    let turn = f32::consts::TAU;
+    angle - turn * ((angle + f32::consts::PI) / turn).floor()
+}
+
+/// Samples the player in player rounds, and saves the dataset when the round is over.
+pub fn record(
+    mut runstate: ResMut<RunState>,
+    bodies: Res<RigidBodySet>,
+    actions: Res<Actions>,
+    mobs: Query<&RigidBodyHandleComponent, With<Mob>>,
+    borgs: Query<(&RigidBodyHandleComponent, &Borg)>,
+    weapons: Query<(&Transform, &AttachedToEntity), (With<Weapon>, With<LooksAt>)>,
+) {
+    let runstate = &mut *runstate;
+    let recorder = match runstate.recorder.as_mut() {
+        Some(recorder) => recorder,
+        None => return,
+    };
+    if let Some(GameState::ArenaOver(Mode::Player)) = runstate.gamestate.entering() {
+        match recorder.dataset.save(&recorder.path) {
+            Ok(()) => println!(
+                "Recorded {} samples to {:?}",
+                recorder.dataset.samples.len(),
+                recorder.path,
+            ),
+            Err(e) => eprintln!("Can't save the recording: {}", e),
+        }
+        return;
+    }
+    if runstate.gamestate.current() != &GameState::Arena(Mode::Player) {
+        return;
+    }
+    let player = match runstate.player {
+        Some(player) => player,
+        None => return,
+    };
+    let (body, borg) = match borgs.get(player) {
+        Ok((handle, borg)) => match bodies.get(handle.handle()) {
+            Some(body) => (body, borg),
+            None => return,
+        },
+        Err(_) => return,
+    };
+
+    let mob_positions: Vec<Point2<f32>> = mobs.iter()
+        .filter_map(|handle| bodies.get(handle.handle()))
+        .map(|body| body.position().translation.vector.into())
+        .collect();
+    let obstacles = runstate.arena.as_ref()
+        .map(|arena| arena.obstacles.as_slice())
+        .unwrap_or(&[]);
+    let position = body.position();
+    let inputs = shooter::Inputs::sense(
+        position,
+        borg.time_alive,
+        &mob_positions,
+        obstacles,
+        runstate.topology,
+    );
+
+    let forward = position.rotation.transform_vector(&Vector2::y());
+    let aim = weapons.iter()
+        .find(|(_transform, owner)| owner.0 == player)
+        .map(|(transform, _owner)| {
+            let weapon_angle = into_isometry_2d(transform.translation, transform.rotation)
+                .rotation.angle();
+            wrap_angle(weapon_angle - position.rotation.angle()) / f32::consts::PI
+        })
+        .unwrap_or(0.0);
+
+    recorder.dataset.samples.push(Sample {
+        inputs: shooter::Brain::normalize_inputs(inputs),
+        walk: (body.linvel().dot(&forward) / borg.speed).max(-1.0).min(1.0),
+        turn: (body.angvel() / borg.rotation_speed).max(-1.0).min(1.0),
+        aim,
+        shoot: actions.pressed(Action::Fire),
+    });
+}
+
+/// Mean squared difference between what the brain would do and what the player did.
+/// Outputs are clamped the same way as when the brain is in control.
+pub fn imitation_error(
+    brain: &shooter::Brain,
+    dataset: &Dataset,
+    buffers: &mut brain::Buffers,
+) -> f32 {
+    if dataset.samples.is_empty() {
+        return 0.0;
+    }
+    let clamp = |v: f32| v.max(-1.0).min(1.0);
+    let total: f32 = dataset.samples.iter()
+        .map(|sample| {
+            buffers.inputs.clear();
+            buffers.inputs.extend_from_slice(&sample.inputs);
+            let outputs = brain.process_normalized(buffers);
+            let aim = wrap_angle(
+                (clamp(outputs.aim_rel_angle) - sample.aim) * f32::consts::PI
+            ) / f32::consts::PI;
+            (clamp(outputs.walk) - sample.walk).powi(2)
+                + (clamp(outputs.turn) - sample.turn).powi(2)
+                + aim.powi(2)
+        })
+        .sum();
+    total / dataset.samples.len() as f32
+}
+
+/// Gradient descent on the imitation error, one step per pass over the dataset.
+/// Clamping of the outputs is treated as if it weren't there,
+/// so that saturated outputs can still learn.
+pub fn fine_tune(
+    mut brain: shooter::Brain,
+    dataset: &Dataset,
+    epochs: u32,
+    rate: f32,
+) -> shooter::Brain {
+    if dataset.samples.is_empty() {
+        return brain;
+    }
+    let clamp = |v: f32| v.max(-1.0).min(1.0);
+    let scale = 2.0 / dataset.samples.len() as f32;
+    let mut gradients = shooter::Gradients::new(&brain);
+    for _ in 0..epochs {
+        gradients.clear();
+        for sample in &dataset.samples {
+            // Outputs are ordered as aim, turn, walk.
+            brain.backprop(
+                &sample.inputs,
+                |outputs| vec![
+                    scale * wrap_angle(
+                        (clamp(outputs[0]) - sample.aim) * f32::consts::PI
+                    ) / f32::consts::PI,
+                    scale * (clamp(outputs[1]) - sample.turn),
+                    scale * (clamp(outputs[2]) - sample.walk),
+                ],
+                &mut gradients,
+            );
+        }
+        brain.descend(&gradients, rate);
+    }
+    brain
+}
+
+/// Evolves a brain to behave like the recorded player,
+/// and then polishes the weights with gradient descent.
+/// Each generation, the best of a handful of mutants replaces the parent if it's better.
+pub fn fit(dataset: &Dataset, generations: u32) -> shooter::Brain {
+    let offspring = 10;
+    let mut buffers = brain::Buffers::default();
+    let mut best = shooter::Brain::new_dumb(3);
+    let mut best_error = imitation_error(&best, dataset, &mut buffers);
+    println!("Imitating {} samples, initial error {:.4}", dataset.samples.len(), best_error);
+    for generation in 0..generations {
+        for _ in 0..offspring {
+            let candidate = best.clone().mutate(0.12);
+            let error = imitation_error(&candidate, dataset, &mut buffers);
+            if error < best_error {
+                best = candidate;
+                best_error = error;
+            }
+        }
+        if generation % 100 == 0 {
+            println!("Generation {}: error {:.4}", generation, best_error);
+        }
+    }
+    let tuned = fine_tune(best.clone(), dataset, 200, 0.05);
+    let tuned_error = imitation_error(&tuned, dataset, &mut buffers);
+    println!("Imitation error {:.4}, fine-tuned {:.4}", best_error, tuned_error);
+    // Steps and clamps can make descent go astray.
+    match tuned_error < best_error {
+        true => tuned,
+        false => best,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn angle_wraps() {
+        assert!((wrap_angle(1.5 * f32::consts::PI) + 0.5 * f32::consts::PI).abs() < 0.001);
+        assert!((wrap_angle(-0.25) + 0.25).abs() < 0.001);
+    }
+
+    #[test]
+    fn fitting_improves() {
+        let dataset = Dataset {
+            samples: (0..20).map(|i| Sample {
+                inputs: vec![i as f32 / 10.0 - 1.0, i as f32, 0.0],
+                walk: 1.0,
+                turn: -0.5,
+                aim: i as f32 / 20.0,
+                shoot: true,
+            }).collect(),
+        };
+        let mut buffers = brain::Buffers::default();
+        let initial = imitation_error(&shooter::Brain::new_dumb(3), &dataset, &mut buffers);
+        let fitted = fit(&dataset, 50);
+        assert!(imitation_error(&fitted, &dataset, &mut buffers) < initial);
+    }
+
+    #[test]
+    fn descent_improves() {
+        let dataset = Dataset {
+            samples: (0..20).map(|i| Sample {
+                inputs: vec![i as f32 / 10.0 - 1.0, 0.5, 0.0],
+                walk: 0.5,
+                turn: 0.0,
+                aim: 0.2,
+                shoot: true,
+            }).collect(),
+        };
+        let mut buffers = brain::Buffers::default();
+        // Descent doesn't connect anything, so start fully connected.
+        let mut brain = shooter::Brain::new_dumb(3);
+        for neuron in brain.hidden_layer.iter_mut().chain(brain.output_layer.iter_mut()) {
+            for weight in neuron.weights.iter_mut() {
+                *weight = 0.1;
+            }
+        }
+        let initial = imitation_error(&brain, &dataset, &mut buffers);
+        let tuned = fine_tune(brain, &dataset, 50, 0.1);
+        assert!(imitation_error(&tuned, &dataset, &mut buffers) < initial);
+    }
+}
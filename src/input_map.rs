@@ -0,0 +1,319 @@
+/*! Keys and buttons for actions.
+ * Bindings are kept in a file, and can be changed on the settings screen. */
+
+/*
+ Author: Dorota Czaplejewicz <gihuac.dcz@porcupinefactory.org>
+ SPDX-License-Identifier: AGPL-3.0-or-later
+ */
+
+use bevy::ecs::{ Res, ResMut };
+use bevy::input::Input;
+use bevy::input::keyboard::KeyCode;
+use bevy::input::mouse::MouseButton;
+use serde::{ Deserialize, Serialize };
+use std::collections::{ BTreeMap, HashSet };
+use std::convert::TryFrom;
+use std::fmt::Write;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+
+/// Kept in the working directory.
+pub const PATH: &str = "input.ron";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Action {
+    Forward,
+    TurnLeft,
+    TurnRight,
+    /// Turns the weapon, for playing without a mouse
+    AimLeft,
+    AimRight,
+    Fire,
+    Weapon1,
+    Weapon2,
+    Weapon3,
+    Weapon4,
+    Pause,
+    /// Continue from the game over screen
+    Confirm,
+    /// Leave to the main menu
+    Back,
+    /// Shows or hides the brain of a shooter as it thinks
+    ToggleViewer,
+    /// Simulation speed, in rounds without a player
+    SpeedUp,
+    SlowDown,
+    /// Stops the simulation without the pause menu
+    Freeze,
+    /// Runs a single tick of a frozen simulation
+    Step,
+    /// Jumps within a replay
+    ScrubBack,
+    ScrubForward,
+    StartPlayer,
+    StartAI,
+    StartCoop,
+    Settings,
+    Quit,
+}
+
+impl Action {
+    /// In the order of the settings screen.
+    pub const ALL: [Action; 25] = [
+        Action::Forward,
+        Action::TurnLeft,
+        Action::TurnRight,
+        Action::AimLeft,
+        Action::AimRight,
+        Action::Fire,
+        Action::Weapon1,
+        Action::Weapon2,
+        Action::Weapon3,
+        Action::Weapon4,
+        Action::Pause,
+        Action::Confirm,
+        Action::Back,
+        Action::ToggleViewer,
+        Action::SpeedUp,
+        Action::SlowDown,
+        Action::Freeze,
+        Action::Step,
+        Action::ScrubBack,
+        Action::ScrubForward,
+        Action::StartPlayer,
+        Action::StartAI,
+        Action::StartCoop,
+        Action::Settings,
+        Action::Quit,
+    ];
+}
+
+/// Names of keys which can be bound.
+const KEYS: &[(&str, KeyCode)] = &[
+    ("A", KeyCode::A), ("B", KeyCode::B), ("C", KeyCode::C), ("D", KeyCode::D),
+    ("E", KeyCode::E), ("F", KeyCode::F), ("G", KeyCode::G), ("H", KeyCode::H),
+    ("I", KeyCode::I), ("J", KeyCode::J), ("K", KeyCode::K), ("L", KeyCode::L),
+    ("M", KeyCode::M), ("N", KeyCode::N), ("O", KeyCode::O), ("P", KeyCode::P),
+    ("Q", KeyCode::Q), ("R", KeyCode::R), ("S", KeyCode::S), ("T", KeyCode::T),
+    ("U", KeyCode::U), ("V", KeyCode::V), ("W", KeyCode::W), ("X", KeyCode::X),
+    ("Y", KeyCode::Y), ("Z", KeyCode::Z),
+    ("0", KeyCode::Key0), ("1", KeyCode::Key1), ("2", KeyCode::Key2), ("3", KeyCode::Key3),
+    ("4", KeyCode::Key4), ("5", KeyCode::Key5), ("6", KeyCode::Key6), ("7", KeyCode::Key7),
+    ("8", KeyCode::Key8), ("9", KeyCode::Key9),
+    ("Up", KeyCode::Up), ("Down", KeyCode::Down), ("Left", KeyCode::Left), ("Right", KeyCode::Right),
+    ("Escape", KeyCode::Escape), ("Return", KeyCode::Return), ("Space", KeyCode::Space),
+    ("Back", KeyCode::Back), ("Tab", KeyCode::Tab),
+    ("LShift", KeyCode::LShift), ("RShift", KeyCode::RShift),
+    ("LControl", KeyCode::LControl), ("RControl", KeyCode::RControl),
+    ("LAlt", KeyCode::LAlt), ("RAlt", KeyCode::RAlt),
+    ("F1", KeyCode::F1), ("F2", KeyCode::F2), ("F3", KeyCode::F3), ("F4", KeyCode::F4),
+    ("F5", KeyCode::F5), ("F6", KeyCode::F6), ("F7", KeyCode::F7), ("F8", KeyCode::F8),
+    ("F9", KeyCode::F9), ("F10", KeyCode::F10), ("F11", KeyCode::F11), ("F12", KeyCode::F12),
+];
+
+const MOUSE_BUTTONS: &[(&str, MouseButton)] = &[
+    ("MouseLeft", MouseButton::Left),
+    ("MouseRight", MouseButton::Right),
+    ("MouseMiddle", MouseButton::Middle),
+];
+
+/// Stored by name.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+}
+
+impl Binding {
+    /// Keys missing from the name list can't be bound.
+    pub fn from_key(key: KeyCode) -> Option<Binding> {
+        KEYS.iter().find(|(_, k)| *k == key).map(|_| Binding::Key(key))
+    }
+
+    pub fn from_mouse(button: MouseButton) -> Option<Binding> {
+        MOUSE_BUTTONS.iter().find(|(_, b)| *b == button).map(|_| Binding::Mouse(button))
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Binding::Key(key) => KEYS.iter()
+                .find(|(_, k)| k == key)
+                .map(|(name, _)| *name),
+            Binding::Mouse(button) => MOUSE_BUTTONS.iter()
+                .find(|(_, b)| b == button)
+                .map(|(name, _)| *name),
+        }.unwrap_or("?")
+    }
+}
+
+impl TryFrom<String> for Binding {
+    type Error = String;
+    fn try_from(name: String) -> Result<Binding, String> {
+        KEYS.iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, key)| Binding::Key(*key))
+            .or_else(|| {
+                MOUSE_BUTTONS.iter()
+                    .find(|(n, _)| *n == name)
+                    .map(|(_, button)| Binding::Mouse(*button))
+            })
+            .ok_or_else(|| format!("Unknown key: {}", name))
+    }
+}
+
+impl From<Binding> for String {
+    fn from(binding: Binding) -> String {
+        binding.name().into()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InputMap {
+    bindings: BTreeMap<Action, Vec<Binding>>,
+}
+
+impl Default for InputMap {
+    fn default() -> InputMap {
+        use Action::*;
+        use Binding::{ Key, Mouse };
+        let bindings = vec![
+            (Forward, vec![Key(KeyCode::W), Key(KeyCode::Up)]),
+            (TurnLeft, vec![Key(KeyCode::A), Key(KeyCode::Left)]),
+            (TurnRight, vec![Key(KeyCode::D), Key(KeyCode::Right)]),
+            (AimLeft, vec![Key(KeyCode::J)]),
+            (AimRight, vec![Key(KeyCode::L)]),
+            (Fire, vec![Mouse(MouseButton::Left)]),
+            (Weapon1, vec![Key(KeyCode::Key1)]),
+            (Weapon2, vec![Key(KeyCode::Key2)]),
+            (Weapon3, vec![Key(KeyCode::Key3)]),
+            (Weapon4, vec![Key(KeyCode::Key4)]),
+            (Pause, vec![Key(KeyCode::Escape)]),
+            (Confirm, vec![Key(KeyCode::Return)]),
+            (Back, vec![Key(KeyCode::Back)]),
+            (ToggleViewer, vec![Key(KeyCode::V)]),
+            (SpeedUp, vec![Key(KeyCode::Tab)]),
+            (SlowDown, vec![Key(KeyCode::Q)]),
+            (Freeze, vec![Key(KeyCode::Space)]),
+            (Step, vec![Key(KeyCode::S)]),
+            (ScrubBack, vec![Key(KeyCode::Left)]),
+            (ScrubForward, vec![Key(KeyCode::Right)]),
+            (StartPlayer, vec![Key(KeyCode::Key1)]),
+            (StartAI, vec![Key(KeyCode::Key2)]),
+            (StartCoop, vec![Key(KeyCode::Key3)]),
+            (Settings, vec![Key(KeyCode::Key4)]),
+            (Quit, vec![Key(KeyCode::Escape)]),
+        ];
+        InputMap {
+            bindings: bindings.into_iter().collect(),
+        }
+    }
+}
+
+impl InputMap {
+    /// A missing file means default bindings.
+    /// Actions missing from the file keep their defaults.
+    pub fn load(path: &Path) -> Result<InputMap, anyhow::Error> {
+        let mut map = InputMap::default();
+        match File::open(path) {
+            Ok(f) => {
+                let loaded: InputMap = ron::de::from_reader(f)?;
+                map.bindings.extend(loaded.bindings);
+                Ok(map)
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(map),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), anyhow::Error> {
+        fs::write(path, ron::ser::to_string_pretty(self, Default::default())?)?;
+        Ok(())
+    }
+
+    pub fn bindings(&self, action: Action) -> &[Binding] {
+        self.bindings.get(&action).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    pub fn bind(&mut self, action: Action, bindings: Vec<Binding>) {
+        self.bindings.insert(action, bindings);
+    }
+
+    /// One line per action.
+    pub fn table(&self) -> String {
+        let mut f = String::new();
+        for action in Action::ALL.iter() {
+            let names: Vec<_> = self.bindings(*action).iter().map(Binding::name).collect();
+            let _ = writeln!(f, "{:?}: {}", action, names.join(" "));
+        }
+        f
+    }
+}
+
+/// Actions triggered in this frame.
+#[derive(Debug, Default)]
+pub struct Actions {
+    pressed: HashSet<Action>,
+    just_pressed: HashSet<Action>,
+}
+
+impl Actions {
+    pub fn pressed(&self, action: Action) -> bool {
+        self.pressed.contains(&action)
+    }
+
+    pub fn just_pressed(&self, action: Action) -> bool {
+        self.just_pressed.contains(&action)
+    }
+}
+
+/// Turns keys and buttons into actions. Runs before anything reads `Actions`.
+pub fn update_actions(
+    map: Res<InputMap>,
+    keys: Res<Input<KeyCode>>,
+    mouse: Res<Input<MouseButton>>,
+    mut actions: ResMut<Actions>,
+) {
+    actions.pressed.clear();
+    actions.just_pressed.clear();
+    for (action, bindings) in map.bindings.iter() {
+        for binding in bindings {
+            let (pressed, just_pressed) = match binding {
+                Binding::Key(key) => (keys.pressed(*key), keys.just_pressed(*key)),
+                Binding::Mouse(button) => (mouse.pressed(*button), mouse.just_pressed(*button)),
+            };
+            if pressed {
+                actions.pressed.insert(*action);
+            }
+            if just_pressed {
+                actions.just_pressed.insert(*action);
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let map = InputMap::default();
+        let text = ron::ser::to_string(&map).unwrap();
+        assert!(text.contains("\"MouseLeft\""));
+        let loaded: InputMap = ron::de::from_str(&text).unwrap();
+        assert_eq!(loaded, map);
+    }
+
+    #[test]
+    fn every_action_bound() {
+        let map = InputMap::default();
+        for action in Action::ALL.iter() {
+            assert!(!map.bindings(*action).is_empty(), "{:?}", action);
+        }
+    }
+}
@@ -4,8 +4,9 @@ use bevy::prelude::*;
 use bevy::sprite::entity::SpriteBundle;
 use bevy_rapier2d::{
     na::Vector2,
+    physics::RigidBodyHandleComponent,
     rapier::{
-        dynamics::RigidBodyBuilder,
+        dynamics::{ RigidBodyBuilder, RigidBodySet },
         geometry::ColliderBuilder,
         //        math::Point,
     },
@@ -13,68 +14,102 @@ use bevy_rapier2d::{
 
 use crate::geometry::into_isometry_2d;
 use super::assets;
+use super::simulation;
 
 
 use crate::rapier::WithBody;
 
 
+/// Fires all projectiles of one shot.
 pub fn spawn(
     commands: &mut Commands,
     asset_server: &Res<AssetServer>,
     assets: &Res<assets::Assets>,
     audio_output: &Res<Audio>,
     transform: &Transform,
+    owner: Entity,
+    weapon: &Weapon,
 ) {
-    let isometry = into_isometry_2d(
-        transform.translation.clone(),
-        transform.rotation.clone()
-    );
-    let v = isometry.rotation * Vector2::y() * 200.0;
-    let body = RigidBodyBuilder::new_dynamic()
-        .position(isometry)
-        .linvel(v.x, v.y);
-    let collider = ColliderBuilder::cuboid(0.25, 1.0).sensor(true);
-    let transform = Transform {
-        translation: Vec3::new(
-            transform.translation.x,
-            transform.translation.y,
-            -4.0,
-        ),
-        rotation: transform.rotation.clone(),
-        scale: Vec3::splat(1.0 / 2.0),
-        ..Default::default()
-    };
-    commands
-        .spawn(SpriteBundle {
-            transform,
-            // Spawn needs to happen before transform in order for the global
-            // to be corrrectly rendered.
-            // But it also must happen after transform to
-            // start from the correct position.
-            // Compromise: update renderer position manually.
-            global_transform: transform.into(),
-            material: assets.projectile.clone().unwrap(),
+    let stats = weapon.kind.stats();
+    for i in 0..stats.projectiles {
+        // Evenly across the spread, centered on the aim
+        let offset = match stats.projectiles {
+            1 => 0.0,
+            count => stats.spread * (i as f32 / (count - 1) as f32 - 0.5),
+        };
+        let rotation = transform.rotation * Quat::from_rotation_z(offset);
+        let isometry = into_isometry_2d(
+            transform.translation.clone(),
+            rotation,
+        );
+        let speed = stats.speed * weapon.speed_factor;
+        let v = isometry.rotation * Vector2::y() * speed;
+        let body = RigidBodyBuilder::new_dynamic()
+            .position(isometry)
+            .linvel(v.x, v.y);
+        let collider = ColliderBuilder::cuboid(0.25, stats.length).sensor(true);
+        let transform = Transform {
+            translation: Vec3::new(
+                transform.translation.x,
+                transform.translation.y,
+                -4.0,
+            ),
+            rotation,
+            scale: Vec3::new(1.0 / 2.0, stats.length / 2.0, 1.0),
             ..Default::default()
-        })
-        .with(Laser {
-            despawn_timer: Timer::from_seconds(5.0, false),
-        })
-        .with_body(body)
-        .with(collider)
-        .with(ForStates::from_func(GameState::is_arena));
+        };
+        commands
+            .spawn(SpriteBundle {
+                transform,
+                // Spawn needs to happen before transform in order for the global
+                // to be corrrectly rendered.
+                // But it also must happen after transform to
+                // start from the correct position.
+                // Compromise: update renderer position manually.
+                global_transform: transform.into(),
+                material: assets.projectile.clone().unwrap(),
+                ..Default::default()
+            })
+            .with(Laser {
+                despawn_timer: Timer::from_seconds(stats.lifetime * weapon.lifetime_factor, false),
+                speed,
+                owner,
+                damage: stats.damage,
+                piercing: stats.piercing,
+                blast_radius: stats.blast_radius,
+                hit: false,
+            })
+            .with_body(body)
+            .with(collider)
+            .with(ForStates::from_func(GameState::is_arena));
+    }
     let sound = asset_server.load("sfx_laser1.mp3");
     audio_output.play(sound);
 }
 
+/// Grenades which didn't hit anything explode at the end of their flight.
 pub fn despawn_laser_system(
     commands: &mut Commands,
     runstate: Res<RunState>,
-    time: Res<Time>,
-    mut query: Query<(Entity, Mut<Laser>)>,
+    mut blasts: ResMut<Events<BlastEvent>>,
+    bodies: Res<RigidBodySet>,
+    mut query: Query<(Entity, Mut<Laser>, &RigidBodyHandleComponent)>,
 ) {
-    for (entity, mut laser) in &mut query.iter_mut() {
-        laser.despawn_timer.tick(time.delta_seconds());
+    for (entity, mut laser, body) in &mut query.iter_mut() {
+        laser.despawn_timer.tick(simulation::TICK);
         if laser.despawn_timer.finished() {
+            // The transform only follows the body once per frame.
+            let position = bodies.get(body.handle())
+                .map(|body| body.position().translation.vector);
+            if let (Some(radius), Some(position)) = (laser.blast_radius, position) {
+                blasts.send(BlastEvent {
+                    x: position.x,
+                    y: position.y,
+                    radius,
+                    damage: laser.damage,
+                    owner: laser.owner,
+                });
+            }
             commands.despawn(entity);
         }
     }
@@ -0,0 +1,100 @@
+/*! Best scores of human players */
+
+/*
+ Author: Dorota Czaplejewicz <gihuac.dcz@porcupinefactory.org>
+ SPDX-License-Identifier: AGPL-3.0-or-later
+ */
+
+use serde::{ Deserialize, Serialize };
+use std::fmt::Write;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+
+/// Kept in the working directory.
+pub const PATH: &str = "highscores.ron";
+/// How many scores are kept.
+const SIZE: usize = 10;
+pub const MAX_NAME_LENGTH: usize = 12;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Score {
+    pub name: String,
+    pub score: u32,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Leaderboard {
+    /// Best first
+    scores: Vec<Score>,
+}
+
+impl Leaderboard {
+    /// A missing file is an empty leaderboard.
+    pub fn load(path: &Path) -> Result<Leaderboard, anyhow::Error> {
+        match File::open(path) {
+            Ok(f) => Ok(ron::de::from_reader(f)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Leaderboard::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), anyhow::Error> {
+        fs::write(path, ron::ser::to_string_pretty(self, Default::default())?)?;
+        Ok(())
+    }
+
+    pub fn qualifies(&self, score: u32) -> bool {
+        score > 0 && (
+            self.scores.len() < SIZE
+            || self.scores.last().map(|s| score > s.score).unwrap_or(true)
+        )
+    }
+
+    /// Returns the place, counting from 0, if the score made it to the table.
+    pub fn insert(&mut self, score: Score) -> Option<usize> {
+        if !self.qualifies(score.score) {
+            return None;
+        }
+        // Older scores stay ahead of equal new ones.
+        let place = self.scores.iter()
+            .position(|s| s.score < score.score)
+            .unwrap_or(self.scores.len());
+        self.scores.insert(place, score);
+        self.scores.truncate(SIZE);
+        Some(place)
+    }
+
+    pub fn table(&self) -> String {
+        let mut f = String::new();
+        for (place, score) in self.scores.iter().enumerate() {
+            let _ = writeln!(f, "{:>2}. {:<12} {:>6}", place + 1, score.name, score.score);
+        }
+        f
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn score(score: u32) -> Score {
+        Score { name: format!("{}", score), score }
+    }
+
+    #[test]
+    fn keeps_best() {
+        let mut board = Leaderboard::default();
+        for i in 1..=SIZE as u32 {
+            assert_eq!(board.insert(score(i * 10)), Some(0));
+        }
+        assert!(!board.qualifies(10));
+        assert_eq!(board.insert(score(15)), Some(9));
+        assert_eq!(board.scores.len(), SIZE);
+        assert_eq!(board.scores.last(), Some(&score(15)));
+        assert_eq!(board.insert(score(0)), None);
+    }
+}
@@ -1,27 +1,44 @@
-use bevy::ecs::SystemStage;
+use bevy::ecs::{ Schedule, SystemStage };
 use bevy::prelude::*;
 use bevy::ui::entity::CameraUiBundle;
 use bevy_rapier2d::na::Vector2;
+use bevy_rapier2d::physics;
 use bevy_rapier2d::physics::RapierConfiguration;
 use bevy_rapier2d::physics::RapierPhysicsPlugin;
+use std::path::Path;
 
 
 mod arena;
 mod assets;
+mod benchmark;
 mod brain;
 mod buttons;
+mod coevolution;
 mod components;
 mod contact;
+mod curriculum;
 mod debug;
 mod explosion;
+mod fitness;
 mod fps;
+mod gamepad;
 mod geometry;
+mod headless;
+mod imitation;
+mod input_map;
 mod laser;
+mod leaderboard;
 mod mob;
+mod novelty;
+mod options;
 //mod paq;
+mod pareto;
 mod player;
 mod rapier;
+mod replay;
+mod scenario;
 mod shooter;
+mod simulation;
 mod state;
 //mod tga;
 //mod treeb;
@@ -29,6 +46,7 @@ mod ui;
 #[macro_use]
 mod util;
 mod viewer;
+mod weapon;
 
 use arena::*;
 use components::*;
@@ -44,7 +62,104 @@ use bevy::ecs::IntoSystem;
 
 
 fn main() {
-    App::build()
+    let options = match options::Options::from_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    };
+    let benchmark = options.benchmark.as_ref().map(|path| {
+        benchmark::Benchmark::load(path, options.genotype, options.min_score)
+            .unwrap_or_else(|e| {
+                eprintln!("Can't load benchmark: {}", e);
+                std::process::exit(1);
+            })
+    });
+    let playback = options.replay.as_ref().map(|path| {
+        replay::Playback::load(path)
+            .unwrap_or_else(|e| {
+                eprintln!("Can't load replay: {}", e);
+                std::process::exit(1);
+            })
+    });
+    let start = match (&playback, &benchmark) {
+        (Some(_), _) => GameState::Replay,
+        (None, Some(_)) => GameState::Arena(Mode::Benchmark),
+        (None, None) => GameState::MainMenu,
+    };
+    let scenario = options.scenario.as_ref().map(|path| {
+        scenario::Scenario::load(path)
+            .unwrap_or_else(|e| {
+                eprintln!("Can't load scenario: {}", e);
+                std::process::exit(1);
+            })
+    });
+    let leaderboard = leaderboard::Leaderboard::load(Path::new(leaderboard::PATH))
+        .unwrap_or_else(|e| {
+            eprintln!("Can't load the leaderboard, starting a new one: {}", e);
+            Default::default()
+        });
+    let recorder = options.record.clone().map(|path| {
+        imitation::Recorder::load(path)
+            .unwrap_or_else(|e| {
+                eprintln!("Can't load recording: {}", e);
+                std::process::exit(1);
+            })
+    });
+    let imitation = options.imitate.as_ref().map(|path| {
+        imitation::Dataset::load(path)
+            .and_then(|dataset| match dataset.samples.is_empty() {
+                true => Err(anyhow::anyhow!("No samples in {:?}", path)),
+                false => Ok(imitation::fit(&dataset, 1000)),
+            })
+            .unwrap_or_else(|e| {
+                eprintln!("Can't load imitation samples: {}", e);
+                std::process::exit(1);
+            })
+    });
+    let replays = options.replays.clone().map(|path| {
+        replay::Recorder::new(path)
+            .unwrap_or_else(|e| {
+                eprintln!("Can't save replays: {}", e);
+                std::process::exit(1);
+            })
+    });
+    let input_map = input_map::InputMap::load(Path::new(input_map::PATH))
+        .unwrap_or_else(|e| {
+            eprintln!("Can't load key bindings, using defaults: {}", e);
+            Default::default()
+        });
+    let headless = options.headless;
+    let mut runstate = RunState::new(start, options);
+    runstate.benchmark = benchmark;
+    runstate.leaderboard = leaderboard;
+    runstate.recorder = recorder;
+    runstate.replays = replays;
+    runstate.playback = playback;
+    if let Some(brain) = imitation {
+        runstate.shooter_gene_pool.seed(shooter::Genotype {
+            brain,
+            body: Default::default(),
+            weapon: Default::default(),
+        });
+    }
+    if let Some(scenario) = scenario {
+        runstate.scenario = scenario;
+    }
+    build_app(runstate, input_map, headless).run();
+}
+
+/// Everything but reading the options.
+fn build_app(runstate: RunState, input_map: input_map::InputMap, headless: bool) -> AppBuilder {
+    let mut clock = simulation::Clock::default();
+    if headless {
+        // Nobody's watching.
+        clock.speed = simulation::Speed::Max;
+    }
+
+    let mut app = App::build();
+    app
         .add_resource(WindowDescriptor {
             title: "Breedmatic".to_string(),
             width: WINDOW_WIDTH as f32,
@@ -54,17 +169,64 @@ fn main() {
         .add_resource(ClearColor(Color::rgb_u8(5, 5, 10)))
         .add_event::<AsteroidSpawnEvent>()
         .add_event::<ExplosionSpawnEvent>()
+        .add_event::<BlastEvent>()
         .add_event::<shooter::BrainFed>()
         .add_plugin(RapierPhysicsPlugin)
         .add_plugin(fps::Plugin)
-        //.add_plugin(viewer::Plugin)
-        .add_plugins(DefaultPlugins)
+        .add_plugin(viewer::Plugin);
+    match headless {
+        true => app.add_plugins_with(DefaultPlugins, headless::default_plugins)
+            .add_plugin(headless::Plugin),
+        false => app.add_plugins(DefaultPlugins),
+    };
+    app
         .add_plugin(buttons::Plugin)
         //.init_asset_loader::<paq::Loader>()
         .add_resource(RapierConfiguration {
             gravity: Vector2::zeros(),
+            // Stepped in the simulation stage instead.
+            physics_pipeline_active: false,
             ..Default::default()
         })
+        // Runs a number of ticks every frame, depending on the simulation speed.
+        // Each tick first settles whatever the previous one spawned or despawned,
+        // so that nothing depends on how many ticks a frame holds.
+        .add_stage_after(
+            stage::UPDATE,
+            "SIMULATE",
+            Schedule::default()
+                .with_run_criteria(simulation::tick.system())
+                .with_stage(
+                    "BODIES",
+                    SystemStage::parallel()
+                        // Rapier's plugin does this only once per frame,
+                        // destroying at the end of one and creating at the start of the next.
+                        // Same order here, or new bodies land in different slots.
+                        .with_system(physics::destroy_body_and_collider_system.system())
+                        .with_system(physics::create_body_and_collider_system.system())
+                        // Whoever died last tick is gone by now.
+                        .with_system(arena::check_end.system()),
+                )
+                .with_stage(
+                    "TICK",
+                    SystemStage::parallel()
+                        .with_system(mob::count_lifetime.system())
+                        // TODO: those should both operate on a copy of mob positions,
+                        // otherwise one will use updated values.
+                        // Maybe use Transform and update Body.
+                        .with_system(mob::think.system())
+                        .with_system(shooter::think.system())
+                        .with_system(components::weapon_repeat.system())
+                        .with_system(projectile::despawn_laser_system.system())
+                        .with_system(arena_spawn.system())
+                        .with_system(simulation::physics_step.system())
+                        // Right after the step, so that whatever got destroyed doesn't get hit again.
+                        .with_system(contact::contact_system.system())
+                        .with_system(spawn_asteroid_system.system())
+                        .with_system(wrap_around.system())
+                        .with_system(replay::record.system()),
+                ),
+        )
         // Following another entity needs to take place
         // after Rapier had its go updating the parent's position.
         .add_stage_after(stage::POST_UPDATE, "FOLLOW", SystemStage::parallel())
@@ -75,46 +237,58 @@ fn main() {
         .add_stage_after("HANDLE_EXPLOSION", "HANDLE_EXIT", SystemStage::parallel())
         .add_stage_after("HANDLE_EXIT", "HANDLE_RUNSTATE", SystemStage::parallel())
         .add_stage_after("HANDLE_RUNSTATE", "CLEANUP", SystemStage::parallel()) // CLEANUP stage required by RapierUtilsPlugin
-        .add_system_to_stage(stage::POST_UPDATE, arena::check_end.system())
-        .add_system(hold_borgs.system())
-        .add_system(mob::count_lifetime.system())
+        .add_system_to_stage(stage::POST_UPDATE, replay::finish_round.system())
         .add_system_to_stage(stage::POST_UPDATE, user_input_system.system())
+        // After the above, so that the key which submits the name doesn't also leave.
+        .add_system_to_stage(stage::POST_UPDATE, ui::leaderboard_input.system())
         .add_system_to_stage(stage::POST_UPDATE, ui::keyboard_menu.system())
         .add_system_to_stage(stage::POST_UPDATE, ui::button_click.system())
         .add_system_to_stage(stage::POST_UPDATE, arena::end_ai_round.system())
-        .add_system_to_stage(stage::POST_UPDATE, arena::start_ai_round.system())
+        .add_system_to_stage(stage::POST_UPDATE, arena::end_benchmark_round.system())
+        .add_system_to_stage(stage::POST_UPDATE, arena::start_next_round.system())
         .add_system(player::point_at_mouse.system())
         .add_system(player::keyboard_walk.system())
+        .add_system(player::gamepad_walk.system())
+        .add_system(player::keyboard_aim.system())
+        .add_system(player::gamepad_aim.system())
+        .add_system(player::switch_weapon.system())
+        .add_system(imitation::record.system())
         .add_system_to_stage("FOLLOW", components::swivel_at.system())
         .add_system_to_stage("FOLLOW", components::follow.system())
         .add_system_to_stage("SHOOT", player::mouse_shoot.system())
-        // TODO: those should both operate on a copy of mob positions,
-        // otherwise one will use updated values.
-        // Maybe use Transform and update Body.
-        .add_system(mob::think.system())
-        .add_system(shooter::think.system())
-        .add_system(components::weapon_repeat.system())
-        .add_system(projectile::despawn_laser_system.system())
         .add_system(explosion::handle.system())
+        .add_system(explosion::hit_flash.system())
         .add_system(setup_arena.system())
-        .add_system(arena_spawn.system())
+        .add_system(simulation::reset_physics.system())
         .add_system(start_menu.system())
         .add_system(game_ui_spawn.system())
         .add_system(ui::score.system())
         .add_system(life_ui_system.system())
         .add_system(gameover_menu.system())
         .add_system(pause_menu.system())
+        .add_system(settings_menu.system())
+        .add_system(ui::settings.system())
+        .add_system(replay::play.system())
+        .add_system(ui::replay_status.system())
         //.add_system(draw_blink_system.system())
         .add_startup_system(assets::setup.system())
         .add_startup_system(setup.system())
-        .add_system_to_stage(stage::POST_UPDATE, contact::contact_system.system())
-        .add_system_to_stage("HANDLE_CONTACT", spawn_asteroid_system.system())
         .add_system_to_stage("HANDLE_EXPLOSION", explosion::spawn.system())
         .add_system_to_stage("HANDLE_RUNSTATE", runstate_fsm.system())
         .add_system_to_stage("HANDLE_EXIT", state_exit_despawn.system())
-        .add_resource(RunState::new(GameState::MainMenu))
+        // Rapier's plugin looks for despawned bodies before HANDLE_EXIT,
+        // and forgets about them by the next frame.
+        .add_system_to_stage("CLEANUP", physics::destroy_body_and_collider_system.system())
+        .add_resource(runstate)
+        .add_resource(gamepad::Lobby::default())
+        .add_resource(input_map)
+        .add_resource(input_map::Actions::default())
+        .add_resource(clock)
+        .add_system_to_stage(stage::PRE_UPDATE, input_map::update_actions.system())
+        .add_system_to_stage(stage::PRE_UPDATE, gamepad::connection.system())
         //.add_plugin(DebugPlugin)
-        .run();
+        .add_system_to_stage(stage::PRE_UPDATE, simulation::controls.system());
+    app
 }
 
 /// UiCamera and Camera2d are spawn once and for all.
@@ -5,7 +5,7 @@
  */
 
 
-use bevy::prelude::{ Mut, Query, Res, ResMut, Time };
+use bevy::prelude::{ Local, Mut, Query, Res, ResMut };
 use bevy_rapier2d::{
     physics::RigidBodyHandleComponent,
     rapier::dynamics::RigidBodySet,
@@ -15,14 +15,20 @@ use rand;
 use rand::distributions::{ Bernoulli, Uniform };
 use rand::distributions::weighted::WeightedIndex;
 use rand_distr::StandardNormal;
+use serde::{ Deserialize, Serialize };
 use std::f32;
+use std::ops::{ Deref, DerefMut };
 use super::arena;
+use super::brain;
 use super::components::{ Borg, Mob };
+use super::geometry::get_nearest;
+use super::simulation;
 use super::state::RunState;
 
 
 use rand::distributions::Distribution;
 use rand::Rng;
+use super::brain::Brain as _;
 
 
 #[derive(Debug)]
@@ -36,7 +42,7 @@ pub struct BrainCommands {
 }
 
 /// Controls mobs by calculating a simple function, and being randomizeable.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Brain {
     /// favorite_angle (bias), angle
     weights: Vec<f32>,
@@ -56,10 +62,15 @@ impl Brain {
             .map(|(a, b)| a * b).sum()
     }
     
-    fn randomize() -> Brain {
+    /// Just chases the borg.
+    pub fn eve() -> Brain {
+        Brain { weights: vec![10.0, 0.0, 0.0] }
+    }
+
+    pub fn randomize_with<R: Rng>(rng: &mut R) -> Brain {
         let distribution = Uniform::new(-1.0, 1.0);
         Brain { weights: {
-            (0..3).map(|_| distribution.sample(&mut rand::thread_rng()))
+            (0..3).map(|_| distribution.sample(rng))
                 .collect()
         }}
     }
@@ -75,13 +86,145 @@ impl Brain {
     }
 }
 
-pub type Genotype = Brain;
+impl brain::Brain for Brain {
+    type Inputs = Inputs;
+    /// Turn speed
+    type Outputs = f32;
+    fn process(&mut self, inputs: Inputs) -> f32 {
+        self.calculate(inputs)
+    }
 
-#[derive(Debug)]
+    fn mutate(self, _strength: f64) -> Brain {
+        Brain::mutate(&self)
+    }
+}
+
+/// What the default body costs. Bodies above that pay for it.
+const BODY_BUDGET: f32 = 3.0;
+
+/// Traits of the body, evolving together with the brain.
+/// Missing traits in files are taken from the default body.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Body {
+    /// Relative to the base speed
+    pub speed: f32,
+    /// Relative to the base rotation speed
+    pub turn_rate: f32,
+    /// Relative to the base radius of the size class
+    pub size: f32,
+    /// Life points on top of what the size gives
+    pub toughness: u32,
+    /// Life points taken from a borg on contact
+    pub damage: u32,
+}
+
+impl Default for Body {
+    fn default() -> Body {
+        Body {
+            speed: 1.0,
+            turn_rate: 1.0,
+            size: 1.0,
+            toughness: 0,
+            damage: 1,
+        }
+    }
+}
+
+impl Body {
+    fn randomize_with<R: Rng>(rng: &mut R) -> Body {
+        Body {
+            speed: rng.gen_range(0.5, 1.5),
+            turn_rate: rng.gen_range(0.5, 1.5),
+            size: rng.gen_range(0.5, 1.5),
+            toughness: rng.gen_range(0, 3),
+            damage: rng.gen_range(1, 3),
+        }
+    }
+
+    fn mutate_with<R: Rng>(&self, rng: &mut R) -> Body {
+        let mut nudge = |v: f32| {
+            (v + rng.sample::<f32, _>(StandardNormal) * 0.1).max(0.25).min(3.0)
+        };
+        let speed = nudge(self.speed);
+        let turn_rate = nudge(self.turn_rate);
+        let size = nudge(self.size);
+        let mut step = |v: u32, min: u32| match rng.gen_range(0, 10) {
+            0 => v.saturating_sub(1).max(min),
+            1 => v + 1,
+            _ => v,
+        };
+        Body {
+            speed,
+            turn_rate,
+            size,
+            toughness: step(self.toughness, 0),
+            damage: step(self.damage, 1),
+        }
+    }
+
+    pub fn cost(&self) -> f32 {
+        self.speed + self.turn_rate + self.size
+            + 0.5 * self.toughness as f32
+            + 0.5 * self.damage.saturating_sub(1) as f32
+    }
+
+    /// How many times over the budget the body is, but at least 1.
+    /// Bodies over the budget are slower, and spawn less often.
+    pub fn upkeep(&self) -> f32 {
+        (self.cost() / BODY_BUDGET).max(1.0)
+    }
+
+    pub fn speed_factor(&self) -> f32 {
+        self.speed / self.upkeep()
+    }
+
+    pub fn turn_factor(&self) -> f32 {
+        self.turn_rate / self.upkeep()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Genotype {
+    pub brain: Brain,
+    #[serde(default)]
+    pub body: Body,
+}
+
+impl Genotype {
+    pub fn eve() -> Genotype {
+        Genotype {
+            brain: Brain::eve(),
+            body: Body::default(),
+        }
+    }
+
+    pub fn randomize_with<R: Rng>(rng: &mut R) -> Genotype {
+        Genotype {
+            brain: Brain::randomize_with(rng),
+            body: Body::randomize_with(rng),
+        }
+    }
+
+    /// Only the body changes. Brains are varied enough through blanks.
+    fn mutate_with<R: Rng>(&self, rng: &mut R) -> Genotype {
+        Genotype {
+            brain: self.brain.clone(),
+            body: self.body.mutate_with(rng),
+        }
+    }
+}
+
+/// Past this many genotypes, the pool forgets the oldest.
+const MAX_POPULATION: usize = 100;
+
+#[derive(Debug, Clone)]
 pub struct GenePool {
     genotypes: Vec<(Genotype, f64)>,
     /// How often spawn a new blank (random) genotype.
     blank_frequency: f64,
+    /// How often a spawned genotype gets its body mutated.
+    mutation_frequency: f64,
 }
 
 impl GenePool {
@@ -89,27 +232,43 @@ impl GenePool {
         GenePool {
             genotypes: vec![
 //                (Brain { weights: vec![f32::consts::TAU, 0.0, 1.0] }, 1.0), // Adam
-                (Brain { weights: vec![10.0, 0.0, 0.0] }, 1.0),// Eve
+                (Genotype::eve(), 1.0),
             ],
             blank_frequency: 0.1,
+            mutation_frequency: 0.2,
+        }
+    }
+
+    /// A pool which never spawns blanks or mutants.
+    pub fn new_frozen(genotypes: Vec<Genotype>) -> GenePool {
+        GenePool {
+            genotypes: genotypes.into_iter().map(|g| (g, 1.0)).collect(),
+            blank_frequency: 0.0,
+            mutation_frequency: 0.0,
         }
     }
 
-    pub fn spawn(&mut self) -> Genotype {
+    pub fn spawn_with<R: Rng>(&mut self, rng: &mut R) -> Genotype {
         let blanks = Bernoulli::new(self.blank_frequency).unwrap();
-        if blanks.sample(&mut rand::thread_rng()) || self.genotypes.is_empty() {
-            Genotype::randomize()
+        if blanks.sample(rng) || self.genotypes.is_empty() {
+            Genotype::randomize_with(rng)
         } else {
+            // Expensive bodies are rarer.
             let distribution = WeightedIndex::new(
-                self.genotypes.iter().map(|(_k, v)| v)
+                self.genotypes.iter().map(|(k, v)| v / k.body.upkeep() as f64)
             ).unwrap();
-            self.genotypes
-                .get_mut(distribution.sample(&mut rand::thread_rng()))
+            let genotype = self.genotypes
+                .get_mut(distribution.sample(rng))
                 .map(|(genotype, weight)| {
                     *weight /= 2.0;
                     genotype.clone()
                 })
-                .unwrap()
+                .unwrap();
+            let mutations = Bernoulli::new(self.mutation_frequency).unwrap();
+            match mutations.sample(rng) {
+                true => genotype.mutate_with(rng),
+                false => genotype,
+            }
         }
     }
 
@@ -118,26 +277,63 @@ impl GenePool {
             .position(|(candidate, _weight)| candidate == &genotype);
         match index {
             Some(idx) => { self.genotypes[idx].1 += 1.0 },
-            None => self.genotypes.push((genotype, 1.0)),
+            None => {
+                self.genotypes.push((genotype, 1.0));
+                if self.genotypes.len() > MAX_POPULATION {
+                    // Overpopulation. The oldest already had their go.
+                    self.genotypes.remove(0);
+                }
+            },
         };
     }
 }
 
+/// Gives access to the brain inside the mob without copying it.
+struct MobBrain<'a>(Mut<'a, Mob>);
+
+impl<'a> Deref for MobBrain<'a> {
+    type Target = Brain;
+    fn deref(&self) -> &Brain {
+        &self.0.genotype.brain
+    }
+}
+
+impl<'a> DerefMut for MobBrain<'a> {
+    fn deref_mut(&mut self) -> &mut Brain {
+        &mut self.0.genotype.brain
+    }
+}
+
+/// Memory reused between think rounds.
+#[derive(Default)]
+pub struct ThinkState {
+    buffers: brain::Buffers,
+    inputs: Vec<Inputs>,
+    outputs: Vec<f32>,
+}
+
 pub fn think(
+    mut state: Local<ThinkState>,
+    runstate: Res<RunState>,
     mut bodies: ResMut<RigidBodySet>,
-    mobs: Query<(&RigidBodyHandleComponent, &Mob)>,
+    mut mobs: Query<(&RigidBodyHandleComponent, Mut<Mob>)>,
     borgs: Query<(&RigidBodyHandleComponent, &Borg)>,
 ) {
-    let borg_position = borgs.iter()
-        .next() // Only take first borg. Should be expanded for multiplayer.
+    let borg_positions: Vec<Point2<f32>> = borgs.iter()
         .map(|(body, _borg)| {
             let body = bodies.get(body.handle()).unwrap();
             Point2::from(body.position().translation.vector)
         })
-        .unwrap_or(Point2::new(0.0, 0.0));
+        .collect();
         
-    for (body, mob) in mobs.iter() {
-        let body = bodies.get_mut(body.handle()).unwrap();
+    state.inputs.clear();
+    for (body, _mob) in mobs.iter_mut() {
+        let body = bodies.get(body.handle()).unwrap();
+        let borg_position = get_nearest(
+            &Point2::from(body.position().translation.vector),
+            &borg_positions,
+            runstate.topology,
+        ).unwrap_or(Point2::new(0.0, 0.0));
         let point: Point2<f32> = body.position().inverse_transform_point(&borg_position);
         let inputs = Inputs {
             angle_to_player: {
@@ -154,8 +350,23 @@ pub fn think(
             },
         };
         //println!("{:?}", inputs);
-        let turn_speed = mob.brain
-            .calculate(inputs)
+        state.inputs.push(inputs);
+    }
+
+    {
+        let ThinkState { buffers, inputs, outputs } = &mut *state;
+        Brain::process_batch(
+            mobs.iter_mut()
+                .map(|(_b, mob)| MobBrain(mob))
+                .zip(inputs.drain(..)),
+            buffers,
+            outputs,
+        );
+    }
+
+    for ((body, mob), turn_speed) in mobs.iter_mut().zip(state.outputs.iter()) {
+        let body = bodies.get_mut(body.handle()).unwrap();
+        let turn_speed = turn_speed
             .min(mob.rotation_speed)
             .max(-mob.rotation_speed);
         //println!("{}", turn_speed);
@@ -167,17 +378,40 @@ pub fn think(
 
 pub fn count_lifetime(
     runstate: Res<RunState>,
-    time: Res<Time>,
-    mut query: Query<Mut<Borg>>,
+    bodies: Res<RigidBodySet>,
+    mut query: Query<(Mut<Borg>, &RigidBodyHandleComponent)>,
 ) {
     if !runstate.gamestate.current().is_live_arena() {
         return;
     }
     
-    // FIXME: This is kind of inaccurate:
-    // the delta when pausing will be different than unpausing.
-    // Maybe switch to a constant tick.
-    for mut borg in &mut query.iter_mut() {
-        borg.time_alive += time.delta_seconds();
+    for (mut borg, body) in &mut query.iter_mut() {
+        borg.time_alive += simulation::TICK;
+        if let Some(body) = bodies.get(body.handle()) {
+            borg.distance += body.linvel().norm() * simulation::TICK;
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn pool_capped() {
+        let mut pool = GenePool::new_eden();
+        let mut rng = StdRng::seed_from_u64(0);
+        let genotypes: Vec<_> = (0..MAX_POPULATION + 10)
+            .map(|_| Genotype::randomize_with(&mut rng))
+            .collect();
+        for genotype in &genotypes {
+            pool.preserve(genotype.clone());
+        }
+        assert_eq!(pool.genotypes.len(), MAX_POPULATION);
+        assert_eq!(pool.genotypes.last().map(|(g, _)| g), genotypes.last());
+        assert!(pool.genotypes.iter().all(|(g, _)| g != &Genotype::eve()));
     }
 }
@@ -0,0 +1,143 @@
+/*! Novelty search: rewarding shooters for behaving differently */
+
+/*
+ Author: Dorota Czaplejewicz <gihuac.dcz@porcupinefactory.org>
+ SPDX-License-Identifier: AGPL-3.0-or-later
+ */
+
+use std::cmp::Ordering;
+use std::f32;
+use super::arena::{ ARENA_HEIGHT, ARENA_WIDTH };
+
+
+/// Trajectory histogram resolution, in cells per side.
+const GRID: usize = 4;
+/// Aim histogram resolution.
+const AIM_BINS: usize = 8;
+
+/// Characterizes what a borg did during its life.
+/// Component, sampled every tick.
+#[derive(Debug, Clone, Default)]
+pub struct Behaviour {
+    /// Ticks spent in each arena cell
+    trajectory: [u32; GRID * GRID],
+    /// Ticks spent aiming in each direction
+    aim: [u32; AIM_BINS],
+    samples: u32,
+    /// Relative to arena size, from 0 to 1.
+    last_position: (f32, f32),
+}
+
+impl Behaviour {
+    /// `aim` is the absolute weapon angle in radians.
+    pub fn sample(&mut self, x: f32, y: f32, aim: f32) {
+        let rel = |v: f32, size: f32| (v / size + 0.5).max(0.0).min(1.0);
+        let (x, y) = (rel(x, ARENA_WIDTH), rel(y, ARENA_HEIGHT));
+        let cell = |v: f32| ((v * GRID as f32) as usize).min(GRID - 1);
+        self.trajectory[cell(y) * GRID + cell(x)] += 1;
+
+        let turn = (aim / f32::consts::TAU).rem_euclid(1.0);
+        self.aim[((turn * AIM_BINS as f32) as usize).min(AIM_BINS - 1)] += 1;
+
+        self.samples += 1;
+        self.last_position = (x, y);
+    }
+
+    /// Final position, then the fraction of time in each cell, then in each aim direction.
+    pub fn descriptor(&self) -> Vec<f64> {
+        let total = self.samples.max(1) as f64;
+        vec![self.last_position.0 as f64, self.last_position.1 as f64].into_iter()
+            .chain(self.trajectory.iter().map(|c| *c as f64 / total))
+            .chain(self.aim.iter().map(|c| *c as f64 / total))
+            .collect()
+    }
+}
+
+fn distance(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b.iter())
+        .map(|(a, b)| (a - b) * (a - b))
+        .sum::<f64>()
+        .sqrt()
+}
+
+/// Behaviours seen before, kept even after their genotypes are gone.
+#[derive(Debug)]
+pub struct Archive {
+    behaviours: Vec<Vec<f64>>,
+    /// How many neighbours count towards novelty
+    k: usize,
+    /// Novelty needed to get into the archive
+    threshold: f64,
+    max_size: usize,
+}
+
+impl Archive {
+    pub fn new() -> Archive {
+        Archive {
+            behaviours: Vec::new(),
+            k: 5,
+            threshold: 0.3,
+            max_size: 500,
+        }
+    }
+
+    /// Mean distance to the nearest neighbours from the archive and the current population.
+    /// The population may contain the behaviour itself; it gets skipped.
+    pub fn novelty(&self, behaviour: &[f64], population: &[&[f64]]) -> f64 {
+        let mut distances: Vec<f64> = self.behaviours.iter()
+            .map(|b| b.as_slice())
+            .chain(population.iter().cloned())
+            .map(|other| distance(behaviour, other))
+            .collect();
+        distances.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        // Drop itself
+        if distances.first() == Some(&0.0) {
+            distances.remove(0);
+        }
+        let nearest = &distances[..self.k.min(distances.len())];
+        match nearest.len() {
+            // Nothing to compare with. Everything is new.
+            0 => self.threshold,
+            len => nearest.iter().sum::<f64>() / len as f64,
+        }
+    }
+
+    /// Remembers the behaviour if it's novel enough.
+    pub fn consider(&mut self, behaviour: &[f64], novelty: f64) {
+        if novelty >= self.threshold {
+            if self.behaviours.len() >= self.max_size {
+                self.behaviours.remove(0);
+            }
+            self.behaviours.push(behaviour.into());
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.behaviours.len()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn descriptor_fractions() {
+        let mut b = Behaviour::default();
+        b.sample(0.0, 0.0, 0.0);
+        b.sample(-ARENA_WIDTH, -ARENA_HEIGHT, f32::consts::PI);
+        let d = b.descriptor();
+        assert_eq!(d.len(), 2 + GRID * GRID + AIM_BINS);
+        assert_eq!(&d[..2], &[0.0, 0.0]);
+        assert_eq!(d[2..].iter().sum::<f64>(), 2.0);
+    }
+
+    #[test]
+    fn novelty_skips_self() {
+        let archive = Archive::new();
+        let a = [0.0, 0.0];
+        let b = [3.0, 4.0];
+        assert_eq!(archive.novelty(&a, &[&a, &b]), 5.0);
+    }
+}
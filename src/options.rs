@@ -0,0 +1,124 @@
+/*! Command line options */
+
+/*
+ Author: Dorota Czaplejewicz <gihuac.dcz@porcupinefactory.org>
+ SPDX-License-Identifier: AGPL-3.0-or-later
+ */
+
+use crate::arena::{ ARENA_HEIGHT, ARENA_WIDTH };
+use crate::fitness;
+use crate::fitness::Fitness;
+use crate::geometry::Topology;
+use crate::shooter::Selection;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+
+const USAGE: &str = "Usage: breedmatic [--fitness kills|survival|accuracy|avoidance|mixed] [--selection weighted|pareto|novelty|novelty+fitness] [--save-pool FILE] [--scenario FILE] [--curriculum] [--record DATASET] [--imitate DATASET] [--replays DIR] [--topology walled|wrap]
+       breedmatic --replay FILE
+       breedmatic --benchmark POOL_FILE [--genotype ID] [--min-score SCORE] [--scenario FILE] [--headless]";
+
+
+fn parse<T: FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value.parse()
+        .map_err(|_| format!("Invalid value for {}: {}\n{}", name, value, USAGE))
+}
+
+#[derive(Debug)]
+pub struct Options {
+    pub fitness: Box<dyn Fitness>,
+    pub selection: Selection,
+    /// Where to keep the shooter pool
+    pub save_pool: Option<PathBuf>,
+    /// Pool file to take the benchmarked genotype from
+    pub benchmark: Option<PathBuf>,
+    /// Genotype to benchmark, instead of the fittest
+    pub genotype: Option<u64>,
+    pub min_score: Option<f64>,
+    /// Run the benchmark without a window, as fast as possible
+    pub headless: bool,
+    /// Wave definitions
+    pub scenario: Option<PathBuf>,
+    /// Adjust difficulty to shooter performance in AI mode
+    pub curriculum: bool,
+    /// Where to add samples of player rounds
+    pub record: Option<PathBuf>,
+    /// Samples to fit a shooter to before starting
+    pub imitate: Option<PathBuf>,
+    /// Where to save replays of rounds
+    pub replays: Option<PathBuf>,
+    /// Replay to play back instead of playing
+    pub replay: Option<PathBuf>,
+    pub topology: Topology,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            fitness: Box::new(fitness::KillScore),
+            selection: Selection::Weighted,
+            save_pool: None,
+            benchmark: None,
+            genotype: None,
+            min_score: None,
+            headless: false,
+            scenario: None,
+            curriculum: false,
+            record: None,
+            imitate: None,
+            replays: None,
+            replay: None,
+            topology: Topology::Walled,
+        }
+    }
+}
+
+impl Options {
+    pub fn from_args<I: Iterator<Item=String>>(mut args: I) -> Result<Options, String> {
+        let mut options = Options::default();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next()
+                .ok_or_else(|| format!("Missing value for {}\n{}", arg, USAGE));
+            match arg.as_str() {
+                "--fitness" => {
+                    let name = value()?;
+                    options.fitness = fitness::from_name(&name)
+                        .ok_or_else(|| format!("Unknown fitness: {}\n{}", name, USAGE))?;
+                },
+                "--selection" => {
+                    options.selection = match value()?.as_str() {
+                        "weighted" => Selection::Weighted,
+                        "pareto" => Selection::Pareto,
+                        "novelty" => Selection::Novelty { fitness_weight: 0.0 },
+                        "novelty+fitness" => Selection::Novelty { fitness_weight: 1.0 },
+                        other => return Err(format!("Unknown selection: {}\n{}", other, USAGE)),
+                    };
+                },
+                "--save-pool" => options.save_pool = Some(value()?.into()),
+                "--benchmark" => options.benchmark = Some(value()?.into()),
+                "--genotype" => options.genotype = Some(parse(&arg, &value()?)?),
+                "--min-score" => options.min_score = Some(parse(&arg, &value()?)?),
+                "--headless" => options.headless = true,
+                "--scenario" => options.scenario = Some(value()?.into()),
+                "--curriculum" => options.curriculum = true,
+                "--record" => options.record = Some(value()?.into()),
+                "--imitate" => options.imitate = Some(value()?.into()),
+                "--replays" => options.replays = Some(value()?.into()),
+                "--replay" => options.replay = Some(value()?.into()),
+                "--topology" => {
+                    options.topology = match value()?.as_str() {
+                        "walled" => Topology::Walled,
+                        "wrap" => Topology::Wrap { width: ARENA_WIDTH, height: ARENA_HEIGHT },
+                        other => return Err(format!("Unknown topology: {}\n{}", other, USAGE)),
+                    };
+                },
+                "--help" => return Err(USAGE.into()),
+                other => return Err(format!("Unknown option: {}\n{}", other, USAGE)),
+            }
+        }
+        if options.headless && options.benchmark.is_none() {
+            return Err(format!("Only benchmarks can run headless\n{}", USAGE));
+        }
+        Ok(options)
+    }
+}
@@ -0,0 +1,193 @@
+/*! Multi-objective ranking, as in NSGA-II.
+ * All objectives are maximized. */
+
+/*
+ Author: Dorota Czaplejewicz <gihuac.dcz@porcupinefactory.org>
+ SPDX-License-Identifier: AGPL-3.0-or-later
+ */
+
+use std::cmp::Ordering;
+use std::f64;
+
+
+/// True if `a` is no worse than `b` in any objective, and better in at least one.
+pub fn dominates(a: &[f64], b: &[f64]) -> bool {
+    let mut better = false;
+    for (a, b) in a.iter().zip(b.iter()) {
+        if a < b {
+            return false;
+        }
+        if a > b {
+            better = true;
+        }
+    }
+    better
+}
+
+/// Splits points into fronts.
+/// The first front is not dominated by anything,
+/// the second one is dominated only by the first one, and so on.
+/// Returns indices into `points`.
+pub fn sort_fronts(points: &[Vec<f64>]) -> Vec<Vec<usize>> {
+    // How many points dominate this one
+    let mut dominated_by: Vec<usize> = vec![0; points.len()];
+    // Which points this one dominates
+    let mut dominating: Vec<Vec<usize>> = vec![Vec::new(); points.len()];
+    for (i, a) in points.iter().enumerate() {
+        for (j, b) in points.iter().enumerate() {
+            if dominates(a, b) {
+                dominating[i].push(j);
+            } else if dominates(b, a) {
+                dominated_by[i] += 1;
+            }
+        }
+    }
+    let mut fronts = Vec::new();
+    let mut current: Vec<usize> = (0..points.len())
+        .filter(|i| dominated_by[*i] == 0)
+        .collect();
+    while !current.is_empty() {
+        let mut next = Vec::new();
+        for i in current.iter() {
+            for j in dominating[*i].iter() {
+                dominated_by[*j] -= 1;
+                if dominated_by[*j] == 0 {
+                    next.push(*j);
+                }
+            }
+        }
+        fronts.push(current);
+        current = next;
+    }
+    fronts
+}
+
+/// How much room there is around each point of the front.
+/// Edge points get infinity, so that they are always preferred.
+/// Returned in the order of `front`.
+pub fn crowding_distances(points: &[Vec<f64>], front: &[usize]) -> Vec<f64> {
+    let mut distances = vec![0.0; front.len()];
+    let objective_count = front.first().map(|i| points[*i].len()).unwrap_or(0);
+    for objective in 0..objective_count {
+        let value = |idx: usize| points[front[idx]][objective];
+        let mut order: Vec<usize> = (0..front.len()).collect();
+        order.sort_by(|a, b| value(*a).partial_cmp(&value(*b)).unwrap_or(Ordering::Equal));
+        let (first, last) = (order[0], order[order.len() - 1]);
+        distances[first] = f64::INFINITY;
+        distances[last] = f64::INFINITY;
+        let span = value(last) - value(first);
+        if span <= 0.0 {
+            continue;
+        }
+        for w in order.windows(3) {
+            distances[w[1]] += (value(w[2]) - value(w[0])) / span;
+        }
+    }
+    distances
+}
+
+/// Position of a point in the population.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rank {
+    /// Index of the front, 0 is best
+    pub front: usize,
+    pub crowding: f64,
+}
+
+impl Rank {
+    /// Lower front wins, then less crowded.
+    pub fn better_than(&self, other: &Rank) -> bool {
+        self.front < other.front
+            || (self.front == other.front && self.crowding > other.crowding)
+    }
+}
+
+/// Ranks every point, in the order of `points`.
+pub fn rank(points: &[Vec<f64>]) -> Vec<Rank> {
+    let mut ranks = vec![Rank { front: 0, crowding: 0.0 }; points.len()];
+    for (front_idx, front) in sort_fronts(points).iter().enumerate() {
+        for (i, crowding) in front.iter().zip(crowding_distances(points, front)) {
+            ranks[*i] = Rank { front: front_idx, crowding };
+        }
+    }
+    ranks
+}
+
+/// Chooses `count` best points: whole fronts while they fit,
+/// then the least crowded ones from the front that doesn't fit.
+pub fn select(points: &[Vec<f64>], count: usize) -> Vec<usize> {
+    let mut selected = Vec::with_capacity(count);
+    for front in sort_fronts(points) {
+        if selected.len() + front.len() <= count {
+            selected.extend(front);
+        } else {
+            let distances = crowding_distances(points, &front);
+            let mut by_crowding: Vec<_> = front.into_iter().zip(distances).collect();
+            by_crowding.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap_or(Ordering::Equal));
+            selected.extend(
+                by_crowding.into_iter()
+                    .take(count - selected.len())
+                    .map(|(i, _)| i)
+            );
+            break;
+        }
+    }
+    selected
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn domination() {
+        assert!(dominates(&[1.0, 1.0], &[0.0, 1.0]));
+        assert!(!dominates(&[1.0, 1.0], &[1.0, 1.0]));
+        assert!(!dominates(&[2.0, 0.0], &[0.0, 2.0]));
+    }
+
+    #[test]
+    fn fronts() {
+        let points = vec![
+            vec![0.0, 0.0],
+            vec![2.0, 0.0],
+            vec![0.0, 2.0],
+            vec![1.0, 1.0],
+            vec![3.0, 3.0],
+        ];
+        let mut fronts = sort_fronts(&points);
+        for front in fronts.iter_mut() {
+            front.sort();
+        }
+        assert_eq!(fronts, vec![vec![4], vec![1, 2, 3], vec![0]]);
+    }
+
+    #[test]
+    fn crowding() {
+        let points = vec![
+            vec![0.0, 4.0],
+            vec![1.0, 3.0],
+            vec![3.0, 1.0],
+            vec![4.0, 0.0],
+        ];
+        let distances = crowding_distances(&points, &[0, 1, 2, 3]);
+        assert_eq!(distances[0], f64::INFINITY);
+        assert_eq!(distances[3], f64::INFINITY);
+        assert_eq!(distances[1], 1.5);
+        assert_eq!(distances[2], 1.5);
+    }
+
+    #[test]
+    fn select_truncates_by_crowding() {
+        let points = vec![
+            vec![0.0, 4.0],
+            vec![1.0, 3.0],
+            vec![1.1, 2.9],
+            vec![4.0, 0.0],
+        ];
+        let mut selected = select(&points, 3);
+        selected.sort();
+        assert_eq!(selected, vec![0, 2, 3]);
+    }
+}
@@ -1,19 +1,24 @@
 use bevy::app::AppExit;
 use bevy::prelude::*;
-use bevy::prelude::{ EventReader, KeyCode };
+use bevy::prelude::EventReader;
+use bevy::input::gamepad::{ GamepadAxis, GamepadAxisType, GamepadButton, GamepadButtonType };
 use bevy::window::CursorMoved;
 use bevy_rapier2d::{
-    physics::{RapierConfiguration, RigidBodyHandleComponent},
+    physics::RigidBodyHandleComponent,
     rapier::dynamics::RigidBodySet,
 };
 use bevy_rapier2d::na::{ Point2, Translation2, Vector2 };
+use std::f32;
 use super::arena;
 use super::assets;
-use super::components::{weapon_trigger, Borg, LooksAt, Weapon};
-use super::state::{ GameState, Mode, RunState };
+use super::components::{weapon_trigger, AttachedToEntity, Borg, LooksAt, Weapon};
+use super::gamepad;
+use super::input_map::{ Action, Actions };
+use super::state::{ GameState, RunState };
+use super::weapon;
 
 
-/// Marks entities walking with the keyboard.
+/// Marks entities walking with the keyboard or the gamepad.
 pub struct KeyboardWalk;
 
 
@@ -55,32 +60,62 @@ pub fn mouse_shoot(
     asset_server: Res<AssetServer>,
     assets: Res<assets::Assets>,
     audio_output: Res<Audio>,
-    mouse_button_input: Res<Input<MouseButton>>,
-    mut weapons: Query<(&Transform, Mut<Weapon>)>,
+    actions: Res<Actions>,
+    gamepads: Res<gamepad::Lobby>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    mut weapons: Query<(&Transform, Mut<Weapon>, &AttachedToEntity), With<LooksAt>>,
+    mut borgs: Query<Mut<Borg>>,
 ) {
     if !runstate.gamestate.current().is_live_arena() {
         return;
     }
-    if mouse_button_input.pressed(MouseButton::Left) {
-        for (transform, mut weapon) in weapons.iter_mut() {
-            weapon_trigger(&mut weapon, transform, &mut commands, &asset_server, &assets, &audio_output);
+    if actions.pressed(Action::Fire)
+        || gamepads.pressed(&gamepad_buttons, GamepadButtonType::RightTrigger2)
+        || gamepads.pressed(&gamepad_buttons, GamepadButtonType::RightTrigger)
+    {
+        for (transform, mut weapon, owner) in weapons.iter_mut() {
+            let fired = weapon_trigger(&mut weapon, transform, owner.0, &mut commands, &asset_server, &assets, &audio_output);
+            if fired > 0 {
+                if let Ok(mut borg) = borgs.get_mut(owner.0) {
+                    borg.shots += fired;
+                }
+            }
+        }
+    }
+}
+
+/// Weapon actions choose the weapon.
+pub fn switch_weapon(
+    actions: Res<Actions>,
+    mut weapons: Query<Mut<Weapon>, With<LooksAt>>,
+) {
+    let keys = [Action::Weapon1, Action::Weapon2, Action::Weapon3, Action::Weapon4];
+    let kind = keys.iter()
+        .zip(weapon::Kind::ALL.iter())
+        .find(|(action, _kind)| actions.just_pressed(**action))
+        .map(|(_action, kind)| *kind);
+    if let Some(kind) = kind {
+        for mut weapon in weapons.iter_mut() {
+            if weapon.kind != kind {
+                weapon.set_kind(kind);
+            }
         }
     }
 }
 
 pub fn keyboard_walk(
-    input: Res<Input<KeyCode>>,
+    actions: Res<Actions>,
     mut bodies: ResMut<RigidBodySet>,
     query: Query<(&RigidBodyHandleComponent, &Borg, &KeyboardWalk)>,
 ) {
-    let speed = if input.pressed(KeyCode::W) || input.pressed(KeyCode::Up) {
+    let speed = if actions.pressed(Action::Forward) {
         1
     } else {
         0
     };
-    let rotation = if input.pressed(KeyCode::A) || input.pressed(KeyCode::Left) {
+    let rotation = if actions.pressed(Action::TurnLeft) {
         1
-    } else if input.pressed(KeyCode::D) || input.pressed(KeyCode::Right) {
+    } else if actions.pressed(Action::TurnRight) {
         -1
     } else {
         0
@@ -107,40 +142,136 @@ pub fn keyboard_walk(
     }
 }
 
+/// Radians per second
+const KEYBOARD_AIM_SPEED: f32 = f32::consts::PI;
+
+/// Turns the weapon around the borg.
+/// Leaves the aim alone until a key is pressed, so that the mouse still works.
+pub fn keyboard_aim(
+    time: Res<Time>,
+    actions: Res<Actions>,
+    bodies: Res<RigidBodySet>,
+    borgs: Query<&RigidBodyHandleComponent, With<KeyboardWalk>>,
+    mut looks_at: Query<(Mut<LooksAt>, &AttachedToEntity)>,
+) {
+    let direction = match (actions.pressed(Action::AimLeft), actions.pressed(Action::AimRight)) {
+        (true, false) => 1.0,
+        (false, true) => -1.0,
+        _ => return,
+    };
+    for (mut looks_at, owner) in looks_at.iter_mut() {
+        let position = borgs.get(owner.0).ok()
+            .and_then(|handle| bodies.get(handle.handle()))
+            .map(|body| Point2::from(body.position().translation.vector));
+        if let Some(position) = position {
+            let offset = looks_at.0 - position;
+            let angle = offset.y.atan2(offset.x)
+                + direction * KEYBOARD_AIM_SPEED * time.delta_seconds();
+            // Far enough that the weapon's offset from the borg doesn't matter.
+            looks_at.0 = position + Vector2::new(angle.cos(), angle.sin()) * 100.0;
+        }
+    }
+}
+
+/// Twin-stick walking: the borg walks where the left stick points, and turns to face there.
+/// Runs after `keyboard_walk`, so that the stick wins when it's used.
+pub fn gamepad_walk(
+    gamepads: Res<gamepad::Lobby>,
+    axes: Res<Axis<GamepadAxis>>,
+    mut bodies: ResMut<RigidBodySet>,
+    query: Query<(&RigidBodyHandleComponent, &Borg, &KeyboardWalk)>,
+) {
+    let stick = match gamepads.stick(&axes, GamepadAxisType::LeftStickX, GamepadAxisType::LeftStickY) {
+        Some(stick) => stick,
+        None => return,
+    };
+    for (body_handle, borg, _walk) in query.iter() {
+        let body = bodies.get_mut(body_handle.handle()).unwrap();
+        // Angle from the y axis, like the rest of the arena.
+        let target = (-stick.x).atan2(stick.y);
+        let mut turn = target - body.position().rotation.angle();
+        if turn > f32::consts::PI {
+            turn -= f32::consts::TAU;
+        } else if turn < -f32::consts::PI {
+            turn += f32::consts::TAU;
+        }
+        // Turn quickly, but not faster than the borg can.
+        body.set_angvel(
+            (turn * 10.0).max(-borg.rotation_speed).min(borg.rotation_speed),
+            true,
+        );
+        body.set_linvel(Vector2::new(stick.x, stick.y) * borg.speed, true);
+    }
+}
+
+/// The right stick points the weapon.
+pub fn gamepad_aim(
+    gamepads: Res<gamepad::Lobby>,
+    axes: Res<Axis<GamepadAxis>>,
+    bodies: Res<RigidBodySet>,
+    borgs: Query<&RigidBodyHandleComponent, With<KeyboardWalk>>,
+    mut looks_at: Query<(Mut<LooksAt>, &AttachedToEntity)>,
+) {
+    let stick = match gamepads.stick(&axes, GamepadAxisType::RightStickX, GamepadAxisType::RightStickY) {
+        Some(stick) => stick.normalize(),
+        None => return,
+    };
+    for (mut looks_at, owner) in looks_at.iter_mut() {
+        let position = borgs.get(owner.0).ok()
+            .and_then(|handle| bodies.get(handle.handle()))
+            .map(|body| body.position().translation.vector);
+        if let Some(position) = position {
+            // Far enough that the weapon's offset from the borg doesn't matter.
+            looks_at.0 = Point2::from(position) + Vector2::new(stick.x, stick.y) * 100.0;
+        }
+    }
+}
+
 pub fn user_input_system(
     mut runstate: ResMut<RunState>,
-    input: Res<Input<KeyCode>>,
-    mut rapier_configuration: ResMut<RapierConfiguration>,
+    actions: Res<Actions>,
+    gamepads: Res<gamepad::Lobby>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
     mut app_exit_events: ResMut<Events<AppExit>>,
 ) {
+    // Keys are for typing in the name then.
+    if runstate.name_entry.is_some() {
+        return;
+    }
+    // The settings screen takes any key for rebinding.
+    if runstate.gamestate.current() == &GameState::Settings {
+        return;
+    }
     if runstate.gamestate.current() != &GameState::MainMenu
-        && input.just_pressed(KeyCode::Back)
+        && actions.just_pressed(Action::Back)
     {
         runstate.gamestate.transit_to(GameState::MainMenu);
     }
 
+    let start = gamepads.just_pressed(&gamepad_buttons, GamepadButtonType::Start);
     match runstate.gamestate.current().clone() {
         GameState::Arena(mode) => {
-            if input.just_pressed(KeyCode::Escape) {
+            if actions.just_pressed(Action::Pause) || start {
                 runstate.gamestate.transit_to(GameState::ArenaPause(mode));
-                rapier_configuration.physics_pipeline_active = false;
             }
         },
         GameState::MainMenu => {
             // Handled in UI
         },
-        GameState::ArenaOver(Mode::Player) => {
-            if input.just_pressed(KeyCode::Return) {
+        GameState::ArenaOver(mode) if mode.has_player() => {
+            if actions.just_pressed(Action::Confirm)
+                || start
+                || gamepads.just_pressed(&gamepad_buttons, GamepadButtonType::South)
+            {
                 runstate.gamestate.transit_to(GameState::MainMenu);
             }
-            if input.just_pressed(KeyCode::Escape) {
+            if actions.just_pressed(Action::Quit) {
                 app_exit_events.send(AppExit);
             }
         },
         GameState::ArenaPause(mode) => {
-            if input.just_pressed(KeyCode::Escape) {
+            if actions.just_pressed(Action::Pause) || start {
                 runstate.gamestate.transit_to(GameState::Arena(mode));
-                rapier_configuration.physics_pipeline_active = true;
             }
         },
         _ => {},
@@ -0,0 +1,593 @@
+/*! Round replays.
+ * A round can be recorded tick by tick: where borgs and mobs were,
+ * which shots were fired, and what hit what.
+ * Playing it back only moves sprites around, without any physics. */
+
+/*
+ Author: Dorota Czaplejewicz <gihuac.dcz@porcupinefactory.org>
+ SPDX-License-Identifier: AGPL-3.0-or-later
+ */
+
+use bevy::app::Events;
+use bevy::ecs::{ Commands, Entity, Local, Mut, Query, Res, ResMut, With };
+use bevy::math::{ Quat, Vec3 };
+use bevy::render::mesh::Mesh;
+use bevy::sprite::ColorMaterial;
+use bevy::sprite::TextureAtlasSprite;
+use bevy::sprite::entity::{ SpriteBundle, SpriteSheetBundle };
+use bevy::transform::components::Transform;
+use bevy_rapier2d::na::Point2;
+use bevy_rapier2d::{
+    physics::RigidBodyHandleComponent,
+    rapier::dynamics::RigidBodySet,
+};
+use serde::{ Deserialize, Serialize };
+use std::collections::{ BTreeMap, HashMap, HashSet };
+use std::fs;
+use std::fs::File;
+use std::path::{ Path, PathBuf };
+use super::assets;
+use super::components::{
+    AttachedToEntity, Borg, ExplosionKind, ExplosionSpawnEvent, Laser, Mob, Weapon,
+};
+use super::arena;
+use super::geometry::{ into_isometry_2d, Shape, Topology };
+use super::input_map::{ Action, Actions };
+use super::simulation::{ Speed, TICK };
+use super::state::{ GameState, RunState, ValidStates };
+
+
+/// Ticks skipped at once when scrubbing.
+const SCRUB_TICKS: usize = 300;
+/// Ticks per frame at max speed.
+const MAX_TICKS_PER_FRAME: usize = 64;
+
+/// Entity, x, y, angle. For borgs, the angle is where the weapon points.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Pose(pub u64, pub f32, pub f32, pub f32);
+
+/// A projectile, which flies straight from where it was fired.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Shot {
+    pub id: u64,
+    pub x: f32,
+    pub y: f32,
+    pub angle: f32,
+    pub speed: f32,
+    pub length: f32,
+    /// How long it flies, unless used up earlier
+    pub ticks: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Contact {
+    /// Mob hit by a projectile or a blast, but alive
+    Hit(u64),
+    Killed(u64),
+    /// Projectile used up on a mob
+    Spent(u64),
+    /// Borg bumped into by a mob
+    Hurt(u64),
+    Died(u64),
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Tick {
+    pub borgs: Vec<Pose>,
+    pub mobs: Vec<Pose>,
+    /// Fired during this tick
+    pub shots: Vec<Shot>,
+    pub contacts: Vec<Contact>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Replay {
+    /// Walls and obstacles of the arena
+    #[serde(default)]
+    pub obstacles: Vec<Shape>,
+    /// Shots fly across the edges the same way as in the round
+    #[serde(default)]
+    pub topology: Topology,
+    /// Sprite scale of every mob in the round
+    pub mob_scales: BTreeMap<u64, f32>,
+    pub ticks: Vec<Tick>,
+    /// Scores of the borgs, in the order they fell
+    pub scores: Vec<u32>,
+}
+
+impl Replay {
+    pub fn load(path: &Path) -> Result<Replay, anyhow::Error> {
+        Ok(ron::de::from_reader(File::open(path)?)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), anyhow::Error> {
+        fs::write(path, ron::ser::to_string(self)?)?;
+        Ok(())
+    }
+}
+
+/// The file is a lot smaller without the digits nobody can see.
+fn round(value: f32, precision: f32) -> f32 {
+    (value / precision).round() * precision
+}
+
+/// Saves every round in a directory, as round-0.ron, round-1.ron, and so on.
+#[derive(Debug)]
+pub struct Recorder {
+    dir: PathBuf,
+    next_index: u32,
+    /// The round being recorded
+    replay: Option<Replay>,
+    /// Projectiles already recorded as shots
+    shots: HashSet<u64>,
+    /// Contacts in the current tick
+    contacts: Vec<Contact>,
+}
+
+impl Recorder {
+    /// Doesn't overwrite replays from earlier runs.
+    pub fn new(dir: PathBuf) -> Result<Recorder, anyhow::Error> {
+        fs::create_dir_all(&dir)?;
+        let next_index = (0..)
+            .find(|i| !dir.join(format!("round-{}.ron", i)).exists())
+            .unwrap();
+        Ok(Recorder {
+            dir,
+            next_index,
+            replay: None,
+            shots: HashSet::new(),
+            contacts: Vec::new(),
+        })
+    }
+
+    pub fn contact(&mut self, contact: Contact) {
+        if self.replay.is_some() {
+            self.contacts.push(contact);
+        }
+    }
+
+    fn finish(&mut self, scores: &[u32]) {
+        self.shots.clear();
+        self.contacts.clear();
+        if let Some(mut replay) = self.replay.take() {
+            replay.scores = scores.into();
+            let path = self.dir.join(format!("round-{}.ron", self.next_index));
+            self.next_index += 1;
+            match replay.save(&path) {
+                Ok(()) => println!("Saved replay {:?}, scores {:?}", path, replay.scores),
+                Err(e) => eprintln!("Can't save the replay {:?}: {}", path, e),
+            }
+        }
+    }
+}
+
+/// Saves the round once it's over.
+/// Runs every frame, because ticks may not run while the round ends.
+pub fn finish_round(mut runstate: ResMut<RunState>) {
+    let runstate = &mut *runstate;
+    let recorder = match runstate.replays.as_mut() {
+        Some(recorder) => recorder,
+        None => return,
+    };
+    if let Some(next) = runstate.gamestate.entering() {
+        if !next.is_live_arena() {
+            recorder.finish(&runstate.fallen_scores);
+        }
+    }
+}
+
+/// Runs every tick, after contacts are handled.
+pub fn record(
+    mut runstate: ResMut<RunState>,
+    bodies: Res<RigidBodySet>,
+    borgs: Query<(Entity, &RigidBodyHandleComponent), With<Borg>>,
+    weapons: Query<(&Transform, &AttachedToEntity), With<Weapon>>,
+    mobs: Query<(Entity, &RigidBodyHandleComponent, &Transform), With<Mob>>,
+    lasers: Query<(Entity, &Laser, &Transform)>,
+) {
+    let runstate = &mut *runstate;
+    let recorder = match runstate.replays.as_mut() {
+        Some(recorder) => recorder,
+        None => return,
+    };
+    if runstate.gamestate.entering().is_some()
+        || !runstate.gamestate.current().is_live_arena()
+    {
+        return;
+    }
+    let current_arena = &runstate.arena;
+    let topology = runstate.topology;
+    let replay = recorder.replay.get_or_insert_with(|| Replay {
+        obstacles: current_arena.as_ref()
+            .map(|arena| arena.obstacles.clone())
+            .unwrap_or_default(),
+        topology,
+        ..Default::default()
+    });
+
+    let borgs = borgs.iter()
+        .filter_map(|(entity, handle)| {
+            let position = bodies.get(handle.handle())?.position().translation;
+            let aim = weapons.iter()
+                .find(|(_transform, owner)| owner.0 == entity)
+                .map(|(transform, _owner)| {
+                    into_isometry_2d(transform.translation, transform.rotation)
+                        .rotation.angle()
+                })
+                .unwrap_or(0.0);
+            Some(Pose(
+                entity.to_bits(),
+                round(position.x, 0.1),
+                round(position.y, 0.1),
+                round(aim, 0.01),
+            ))
+        })
+        .collect();
+
+    let mut mob_poses = Vec::new();
+    for (entity, handle, transform) in mobs.iter() {
+        if let Some(body) = bodies.get(handle.handle()) {
+            let position = body.position();
+            replay.mob_scales.entry(entity.to_bits()).or_insert(transform.scale.x);
+            mob_poses.push(Pose(
+                entity.to_bits(),
+                round(position.translation.x, 0.1),
+                round(position.translation.y, 0.1),
+                round(position.rotation.angle(), 0.01),
+            ));
+        }
+    }
+
+    let mut shots = Vec::new();
+    let mut present = HashSet::new();
+    for (entity, laser, transform) in lasers.iter() {
+        let id = entity.to_bits();
+        present.insert(id);
+        if recorder.shots.contains(&id) {
+            continue;
+        }
+        let remaining = laser.despawn_timer.duration() - laser.despawn_timer.elapsed();
+        shots.push(Shot {
+            id,
+            x: round(transform.translation.x, 0.1),
+            y: round(transform.translation.y, 0.1),
+            angle: round(
+                into_isometry_2d(transform.translation, transform.rotation).rotation.angle(),
+                0.001,
+            ),
+            speed: laser.speed,
+            // Sprites are 2 units long before scaling.
+            length: transform.scale.y * 2.0,
+            ticks: (remaining / TICK).round() as u32,
+        });
+    }
+    recorder.shots = present;
+
+    replay.ticks.push(Tick {
+        borgs,
+        mobs: mob_poses,
+        shots,
+        contacts: recorder.contacts.drain(..).collect(),
+    });
+}
+
+/// A shot, flying between the ticks.
+#[derive(Debug, Clone, PartialEq)]
+struct Flight {
+    first: usize,
+    /// The first tick it's gone
+    end: usize,
+    shot: Shot,
+}
+
+#[derive(Debug)]
+pub struct Playback {
+    replay: Replay,
+    /// Index of the shown tick
+    pub tick: usize,
+    pub speed: Speed,
+    pub paused: bool,
+    flights: Vec<Flight>,
+}
+
+impl Playback {
+    pub fn load(path: &Path) -> Result<Playback, anyhow::Error> {
+        let replay = Replay::load(path)?;
+        if replay.ticks.is_empty() {
+            return Err(anyhow::anyhow!("The replay is empty"));
+        }
+        Ok(Playback::new(replay))
+    }
+
+    fn new(replay: Replay) -> Playback {
+        let mut flights = Vec::new();
+        for (first, tick) in replay.ticks.iter().enumerate() {
+            for shot in &tick.shots {
+                let end = first + shot.ticks as usize;
+                let spent = replay.ticks[first..end.min(replay.ticks.len())].iter()
+                    .position(|tick| tick.contacts.contains(&Contact::Spent(shot.id)));
+                flights.push(Flight {
+                    first,
+                    end: match spent {
+                        Some(offset) => first + offset + 1,
+                        None => end,
+                    },
+                    shot: shot.clone(),
+                });
+            }
+        }
+        Playback {
+            replay,
+            tick: 0,
+            speed: Speed::X1,
+            paused: false,
+            flights,
+        }
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.replay.ticks.len() as f32 * TICK
+    }
+
+    pub fn time(&self) -> f32 {
+        self.tick as f32 * TICK
+    }
+
+    fn last_tick(&self) -> usize {
+        self.replay.ticks.len() - 1
+    }
+
+    /// Projectiles in the air: id, x, y, angle, length.
+    fn shots_at(&self, tick: usize) -> impl Iterator<Item=(u64, f32, f32, f32, f32)> + '_ {
+        self.flights.iter()
+            .filter(move |flight| flight.first <= tick && tick < flight.end)
+            .map(move |flight| {
+                let shot = &flight.shot;
+                let distance = shot.speed * (tick - flight.first) as f32 * TICK;
+                let point = Point2::new(
+                    shot.x - shot.angle.sin() * distance,
+                    shot.y + shot.angle.cos() * distance,
+                );
+                let point = self.replay.topology.wrap(&point).unwrap_or(point);
+                (
+                    shot.id,
+                    point.x,
+                    point.y,
+                    shot.angle,
+                    shot.length,
+                )
+            })
+    }
+}
+
+/// Sprites on screen, by the entity they stand for in the replay.
+#[derive(Default)]
+pub struct PlaybackState {
+    borgs: HashMap<u64, Entity>,
+    mobs: HashMap<u64, Entity>,
+    shots: HashMap<u64, Entity>,
+}
+
+fn replay_states() -> ValidStates {
+    ValidStates::from_func(|state| state == &GameState::Replay)
+}
+
+/// Moves sprites with the sprite for every id,
+/// spawns missing ones, and despawns the ones which are gone.
+fn sync<F: FnMut(&mut Commands, Transform) -> Entity>(
+    commands: &mut Commands,
+    sprites: &mut HashMap<u64, Entity>,
+    transforms: &mut Query<Mut<Transform>>,
+    poses: Vec<(u64, Transform)>,
+    mut spawn: F,
+) {
+    let present: HashSet<u64> = poses.iter().map(|(id, _)| *id).collect();
+    sprites.retain(|id, entity| {
+        let keep = present.contains(id);
+        if !keep {
+            commands.despawn(*entity);
+        }
+        keep
+    });
+    for (id, transform) in poses {
+        match sprites.get(&id) {
+            Some(entity) => {
+                if let Ok(mut current) = transforms.get_mut(*entity) {
+                    *current = transform;
+                }
+            },
+            None => {
+                let entity = spawn(commands, transform);
+                sprites.insert(id, entity);
+            },
+        }
+    }
+}
+
+/// Shows the replay loaded at the start, and takes the playback controls.
+pub fn play(
+    commands: &mut Commands,
+    mut runstate: ResMut<RunState>,
+    mut state: Local<PlaybackState>,
+    actions: Res<Actions>,
+    assets: Res<assets::Assets>,
+    mut materials: ResMut<bevy::asset::Assets<ColorMaterial>>,
+    mut meshes: ResMut<bevy::asset::Assets<Mesh>>,
+    mut explosions: ResMut<Events<ExplosionSpawnEvent>>,
+    mut transforms: Query<Mut<Transform>>,
+) {
+    if let Some(GameState::Replay) = runstate.gamestate.entering() {
+        // Sprites from the last time are gone with the state.
+        *state = PlaybackState::default();
+        if let Some(playback) = runstate.playback.as_mut() {
+            playback.tick = 0;
+            let material = materials.add(arena::obstacle_color().into());
+            for shape in &playback.replay.obstacles {
+                arena::draw_obstacle(commands, &mut meshes, material.clone(), shape)
+                    .with(replay_states());
+            }
+        }
+        return;
+    }
+    if runstate.gamestate.current() != &GameState::Replay {
+        return;
+    }
+    let playback = match runstate.playback.as_mut() {
+        Some(playback) => playback,
+        None => return,
+    };
+
+    if actions.just_pressed(Action::SpeedUp) {
+        playback.speed = playback.speed.faster();
+    }
+    if actions.just_pressed(Action::SlowDown) {
+        playback.speed = playback.speed.slower();
+    }
+    if actions.just_pressed(Action::Freeze) {
+        playback.paused = !playback.paused;
+    }
+    let last = playback.last_tick();
+    let start = playback.tick;
+    // Contacts are shown only for ticks which get played, not skipped.
+    let mut played = start + 1..start + 1;
+    if actions.just_pressed(Action::ScrubBack) {
+        playback.tick = playback.tick.saturating_sub(SCRUB_TICKS);
+    } else if actions.just_pressed(Action::ScrubForward) {
+        playback.tick = (playback.tick + SCRUB_TICKS).min(last);
+    } else if actions.just_pressed(Action::Step) {
+        playback.paused = true;
+        playback.tick = (playback.tick + 1).min(last);
+        played = start + 1..playback.tick + 1;
+    } else if !playback.paused {
+        let count = playback.speed.ticks_per_frame()
+            .map(|count| count as usize)
+            .unwrap_or(MAX_TICKS_PER_FRAME);
+        playback.tick = (playback.tick + count).min(last);
+        played = start + 1..playback.tick + 1;
+    }
+
+    for tick in &playback.replay.ticks[played] {
+        for contact in &tick.contacts {
+            let (kind, id, poses) = match contact {
+                Contact::Hit(id) | Contact::Killed(id)
+                    => (ExplosionKind::LaserOnAsteroid, id, &tick.mobs),
+                Contact::Hurt(id) => (ExplosionKind::ShipContact, id, &tick.borgs),
+                Contact::Died(id) => (ExplosionKind::ShipDead, id, &tick.borgs),
+                Contact::Spent(_) => continue,
+            };
+            if let Some(Pose(_, x, y, _)) = poses.iter().find(|pose| pose.0 == *id) {
+                explosions.send(ExplosionSpawnEvent { kind, x: *x, y: *y });
+            }
+        }
+    }
+
+    let tick = &playback.replay.ticks[playback.tick];
+    let pose_transform = |Pose(id, x, y, angle): &Pose, z: f32, scale: f32| (
+        *id,
+        Transform {
+            translation: Vec3::new(*x, *y, z),
+            rotation: Quat::from_rotation_z(*angle),
+            scale: Vec3::splat(scale),
+        },
+    );
+
+    let borgs = tick.borgs.iter()
+        .map(|pose| pose_transform(pose, 0.0, 1.0 / 8.0))
+        .collect();
+    let material = assets.borg.clone().unwrap();
+    sync(commands, &mut state.borgs, &mut transforms, borgs, |commands, transform| {
+        commands
+            .spawn(SpriteBundle {
+                transform,
+                global_transform: transform.into(),
+                material: material.clone(),
+                ..Default::default()
+            })
+            .with(replay_states())
+            .current_entity().unwrap()
+    });
+
+    let scales = &playback.replay.mob_scales;
+    let mobs = tick.mobs.iter()
+        .map(|pose| pose_transform(pose, -5.0, scales.get(&pose.0).cloned().unwrap_or(1.0)))
+        .collect();
+    let atlas = assets.louse.clone().unwrap();
+    sync(commands, &mut state.mobs, &mut transforms, mobs, |commands, transform| {
+        commands
+            .spawn(SpriteSheetBundle {
+                texture_atlas: atlas.clone(),
+                sprite: TextureAtlasSprite::new(0),
+                transform,
+                global_transform: transform.into(),
+                ..Default::default()
+            })
+            .with(replay_states())
+            .current_entity().unwrap()
+    });
+
+    let shots = playback.shots_at(playback.tick)
+        .map(|(id, x, y, angle, length)| (
+            id,
+            Transform {
+                translation: Vec3::new(x, y, -4.0),
+                rotation: Quat::from_rotation_z(angle),
+                scale: Vec3::new(1.0 / 2.0, length / 2.0, 1.0),
+            },
+        ))
+        .collect();
+    let material = assets.projectile.clone().unwrap();
+    sync(commands, &mut state.shots, &mut transforms, shots, |commands, transform| {
+        commands
+            .spawn(SpriteBundle {
+                transform,
+                global_transform: transform.into(),
+                material: material.clone(),
+                ..Default::default()
+            })
+            .with(replay_states())
+            .current_entity().unwrap()
+    });
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shot(id: u64, ticks: u32) -> Shot {
+        Shot { id, x: 0.0, y: 0.0, angle: 0.0, speed: 60.0, length: 1.0, ticks }
+    }
+
+    #[test]
+    fn spent_shots_end() {
+        let mut ticks = vec![Tick::default(); 10];
+        ticks[1].shots = vec![shot(1, 5), shot(2, 5)];
+        ticks[3].contacts = vec![Contact::Spent(1)];
+        let playback = Playback::new(Replay {
+            ticks,
+            ..Default::default()
+        });
+        let ids = |tick| playback.shots_at(tick).map(|s| s.0).collect::<Vec<_>>();
+        assert_eq!(ids(0), Vec::<u64>::new());
+        assert_eq!(ids(3), vec![1, 2]);
+        assert_eq!(ids(4), vec![2]);
+        assert_eq!(ids(6), Vec::<u64>::new());
+        // Flies up, one unit per tick.
+        let (_, x, y, _, _) = playback.shots_at(3).next().unwrap();
+        assert!(x.abs() < 0.001 && (y - 2.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut replay = Replay::default();
+        replay.mob_scales.insert(3, 0.5);
+        replay.ticks.push(Tick {
+            borgs: vec![Pose(1, 2.5, -3.0, 0.25)],
+            mobs: vec![Pose(3, 0.0, 0.0, 0.0)],
+            shots: vec![shot(4, 30)],
+            contacts: vec![Contact::Killed(3)],
+        });
+        let text = ron::ser::to_string(&replay).unwrap();
+        assert_eq!(ron::de::from_str::<Replay>(&text).unwrap(), replay);
+    }
+}
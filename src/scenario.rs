@@ -0,0 +1,248 @@
+/*! Scripted waves of mobs.
+ * Positions are relative to the arena size, with (0, 0) in the centre,
+ * and the edges at -0.5 and 0.5. Times are in seconds since the round started. */
+
+/*
+ Author: Dorota Czaplejewicz <gihuac.dcz@porcupinefactory.org>
+ SPDX-License-Identifier: AGPL-3.0-or-later
+ */
+
+use rand::Rng;
+use serde::Deserialize;
+use std::fs::File;
+use std::path::Path;
+use super::arena::{ ARENA_HEIGHT, ARENA_WIDTH };
+use super::components::AsteroidSize;
+use super::geometry::Shape;
+use super::mob;
+
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub enum Edge {
+    Top,
+    Bottom,
+    Left,
+    Right,
+    /// Any of the above
+    Any,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub enum Placement {
+    /// Anywhere in the arena.
+    Anywhere,
+    /// Anywhere but the central area, where borgs start.
+    OutsideCentre,
+    At(f32, f32),
+    Edge(Edge),
+}
+
+impl Placement {
+    /// Relative position.
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> (f32, f32) {
+        match self {
+            Placement::Anywhere => (rng.gen_range(-0.5, 0.5), rng.gen_range(-0.5, 0.5)),
+            Placement::OutsideCentre => loop {
+                let x: f32 = rng.gen_range(-0.5, 0.5);
+                let y: f32 = rng.gen_range(-0.5, 0.5);
+                if x.abs() > 0.25 || y.abs() > 0.25 {
+                    break (x, y);
+                }
+            },
+            Placement::At(x, y) => (*x, *y),
+            Placement::Edge(edge) => {
+                let along: f32 = rng.gen_range(-0.5, 0.5);
+                let edge = match edge {
+                    Edge::Any => [Edge::Top, Edge::Bottom, Edge::Left, Edge::Right][rng.gen_range(0, 4)],
+                    other => *other,
+                };
+                match edge {
+                    Edge::Top => (along, 0.5),
+                    Edge::Bottom => (along, -0.5),
+                    Edge::Left => (-0.5, along),
+                    Edge::Right | Edge::Any => (0.5, along),
+                }
+            },
+        }
+    }
+}
+
+/// Where mob genotypes come from.
+#[derive(Debug, Clone, Deserialize)]
+pub enum BrainSource {
+    /// The gene pool, or the opponents chosen for the round.
+    Pool,
+    Random,
+    Fixed(mob::Genotype),
+}
+
+/// Mobs per second, as time passes.
+#[derive(Debug, Clone, Deserialize)]
+pub enum Rate {
+    Constant(f32),
+    Linear { initial: f32, per_second: f32 },
+    /// Doubles every `doubling` seconds.
+    Exponential { initial: f32, doubling: f32 },
+}
+
+impl Rate {
+    /// `time` is since the start of the stream.
+    pub fn at(&self, time: f32) -> f32 {
+        match self {
+            Rate::Constant(rate) => *rate,
+            Rate::Linear { initial, per_second } => (initial + per_second * time).max(0.0),
+            Rate::Exponential { initial, doubling } => initial * 2.0f32.powf(time / doubling),
+        }
+    }
+}
+
+/// Mobs appearing all at once.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Wave {
+    pub time: f32,
+    pub size: AsteroidSize,
+    pub count: u32,
+    pub placement: Placement,
+    pub brain: BrainSource,
+}
+
+/// Mobs appearing at random moments, at a given rate.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Stream {
+    pub start: f32,
+    /// Never stops if missing.
+    #[serde(default)]
+    pub end: Option<f32>,
+    pub rate: Rate,
+    pub size: AsteroidSize,
+    pub placement: Placement,
+    pub brain: BrainSource,
+}
+
+impl Stream {
+    /// Mobs per second.
+    pub fn rate_at(&self, time: f32) -> f32 {
+        let active = time >= self.start
+            && self.end.map(|end| time < end).unwrap_or(true);
+        match active {
+            true => self.rate.at(time - self.start),
+            false => 0.0,
+        }
+    }
+}
+
+/// Something solid inside the arena. Circle radius is relative to the arena width.
+#[derive(Debug, Clone, Deserialize)]
+pub enum Obstacle {
+    /// Centre and size
+    Rect { x: f32, y: f32, width: f32, height: f32 },
+    Circle { x: f32, y: f32, radius: f32 },
+}
+
+impl Obstacle {
+    /// In arena coordinates.
+    pub fn shape(&self) -> Shape {
+        match self {
+            Obstacle::Rect { x, y, width, height } => Shape::Rect {
+                x: x * ARENA_WIDTH,
+                y: y * ARENA_HEIGHT,
+                half_width: width * ARENA_WIDTH / 2.0,
+                half_height: height * ARENA_HEIGHT / 2.0,
+            },
+            Obstacle::Circle { x, y, radius } => Shape::Circle {
+                x: x * ARENA_WIDTH,
+                y: y * ARENA_HEIGHT,
+                radius: radius * ARENA_WIDTH,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Scenario {
+    #[serde(default)]
+    pub waves: Vec<Wave>,
+    #[serde(default)]
+    pub streams: Vec<Stream>,
+    #[serde(default)]
+    pub obstacles: Vec<Obstacle>,
+}
+
+impl Scenario {
+    pub fn load(path: &Path) -> Result<Scenario, anyhow::Error> {
+        let mut scenario: Scenario = ron::de::from_reader(File::open(path)?)?;
+        scenario.waves.sort_by(|a, b| {
+            a.time.partial_cmp(&b.time).unwrap_or(std::cmp::Ordering::Equal)
+        });
+        Ok(scenario)
+    }
+}
+
+impl Default for Scenario {
+    /// Small fleas outside the centre, doubling every 30s,
+    /// and occasionally a bigger one after a while.
+    fn default() -> Scenario {
+        Scenario {
+            waves: Vec::new(),
+            obstacles: Vec::new(),
+            streams: vec![
+                Stream {
+                    start: 0.0,
+                    end: None,
+                    // 3/4 of the arena is outside the centre
+                    rate: Rate::Exponential { initial: 0.5 * 0.75, doubling: 30.0 },
+                    size: AsteroidSize::Small,
+                    placement: Placement::OutsideCentre,
+                    brain: BrainSource::Pool,
+                },
+                Stream {
+                    start: 15.0,
+                    end: None,
+                    rate: Rate::Constant(0.1),
+                    size: AsteroidSize::Medium,
+                    placement: Placement::Edge(Edge::Any),
+                    brain: BrainSource::Pool,
+                },
+                Stream {
+                    start: 30.0,
+                    end: None,
+                    rate: Rate::Constant(0.05),
+                    size: AsteroidSize::Big,
+                    placement: Placement::Edge(Edge::Any),
+                    brain: BrainSource::Pool,
+                },
+            ],
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn examples_parse() {
+        let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("scenarios");
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            Scenario::load(&path).expect(&format!("{:?}", path));
+        }
+    }
+
+    #[test]
+    fn stream_window() {
+        let stream = Stream {
+            start: 10.0,
+            end: Some(20.0),
+            rate: Rate::Linear { initial: 1.0, per_second: 0.5 },
+            size: AsteroidSize::Small,
+            placement: Placement::Anywhere,
+            brain: BrainSource::Pool,
+        };
+        assert_eq!(stream.rate_at(5.0), 0.0);
+        assert_eq!(stream.rate_at(12.0), 2.0);
+        assert_eq!(stream.rate_at(20.0), 0.0);
+    }
+}
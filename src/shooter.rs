@@ -4,27 +4,41 @@
  SPDX-License-Identifier: AGPL-3.0-or-later
  */
 
+use anyhow::anyhow;
 use bevy::app::Events;
 use bevy::asset::AssetServer;
 use bevy::audio::Audio;
-use bevy::ecs::{ Commands, Entity, Mut, Query, Res, ResMut, Without };
+use bevy::ecs::{ Commands, Entity, Local, Mut, Query, Res, ResMut, Without };
 use bevy::math::{ Quat, Vec3 };
 use bevy::transform::components::Transform;
 use bevy_rapier2d::na::{ Point2, Vector2 };
 use bevy_rapier2d::{
     physics::RigidBodyHandleComponent,
     rapier::dynamics::RigidBodySet,
+    rapier::math::Isometry,
 };
 use rand::distributions::{ Bernoulli, WeightedIndex };
 use rand_distr::{ Binomial, StandardNormal };
+use serde::{ Deserialize, Serialize };
+use std::cmp::Ordering;
 use std::f32;
 use std::fmt;
+use std::fs;
+use std::fs::File;
 use std::io;
+use std::mem;
+use std::ops::DerefMut;
+use std::path::Path;
 use super::assets;
 use super::brain;
 use super::brain::{ Function, Neuron };
+use super::fitness;
+use super::novelty;
+use super::pareto;
+use super::weapon;
 use super::components::{ weapon_trigger, AttachedToEntity, Borg, LooksAt, Mob, Weapon };
-use super::geometry::{ angle_from, get_nearest };
+use super::geometry::{ angle_from, get_nearest, ray_distance, Shape, Topology };
+use super::state::RunState;
 
 
 use crate::brain::MixableGenotype;
@@ -39,10 +53,12 @@ const BARELY_CONNECTED: f32 = 0.001;
 const UNCONNECTED: f32 = 0.0;
 
 
-/// Process a fully connected layer
-fn process_layer(neurons: &[Neuron], mut inputs: Vec<f32>) -> Vec<f32> {
+/// Process a fully connected layer.
+/// The bias gets appended to inputs, and the results replace the contents of `outputs`.
+fn process_layer(neurons: &[Neuron], inputs: &mut Vec<f32>, outputs: &mut Vec<f32>) {
     inputs.push(1.0);
-    neurons.iter().map(|n| n.feed(&inputs)).collect()
+    outputs.clear();
+    outputs.extend(neurons.iter().map(|n| n.feed(inputs)));
 }
 
 
@@ -109,7 +125,7 @@ pub enum Signal {
 
 /// Brain used by the last stand hero
 /// Uses a single hidden layer of neurons
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Brain {
     // TODO: remove those pubs. They are needed for drawing, which should be here anyway.
     pub hidden_layer: Vec<Neuron>,
@@ -128,7 +144,110 @@ impl Brain {
     }
 
     pub fn normalize_inputs(inputs: Inputs) -> Vec<f32> {
-        vec![inputs.mob_rel_angle, inputs.time_survived]
+        let mut out = Vec::with_capacity(INPUT_COUNT as usize + 1);
+        Brain::normalize_inputs_into(inputs, &mut out);
+        out
+    }
+
+    fn normalize_inputs_into(inputs: Inputs, out: &mut Vec<f32>) {
+        out.push(inputs.mob_rel_angle);
+        out.push(inputs.time_survived);
+        out.push(inputs.obstacle_ahead);
+    }
+
+    /// Brains saved before an input got added
+    /// get an unconnected synapse for each missing input.
+    pub fn fit_inputs(&mut self) {
+        for neuron in self.hidden_layer.iter_mut() {
+            while neuron.weights.len() < INPUT_COUNT as usize + 1 {
+                // The bias comes last.
+                let bias = neuron.weights.len() - 1;
+                neuron.weights.insert(bias, UNCONNECTED);
+            }
+        }
+    }
+
+    /// Like `process`, but reuses memory.
+    fn process_buffered(&self, inputs: Inputs, buffers: &mut brain::Buffers) -> Outputs {
+        buffers.inputs.clear();
+        Brain::normalize_inputs_into(inputs, &mut buffers.inputs);
+        self.process_normalized(buffers)
+    }
+
+    /// Takes inputs which were already normalized into `buffers.inputs`.
+    pub fn process_normalized(&self, buffers: &mut brain::Buffers) -> Outputs {
+        process_layer(&self.hidden_layer, &mut buffers.inputs, &mut buffers.outputs);
+        mem::swap(&mut buffers.inputs, &mut buffers.outputs);
+        process_layer(&self.output_layer, &mut buffers.inputs, &mut buffers.outputs);
+        let outputs = &buffers.outputs;
+        Outputs {
+            walk: outputs[2],
+            turn: outputs[1],
+            shoot: true,
+            aim_rel_angle: outputs[0],
+        }
+    }
+
+    /// Adds the gradient of some loss over every weight to `gradients`.
+    /// `inputs` are normalized, without the bias.
+    /// `loss_gradient` gets the outputs, in the order of the output layer,
+    /// and returns the slope of the loss over each of them.
+    /// Returns the outputs.
+    pub fn backprop<F>(&self, inputs: &[f32], loss_gradient: F, gradients: &mut Gradients) -> Vec<f32>
+        where F: FnOnce(&[f32]) -> Vec<f32>
+    {
+        let mut inputs = inputs.to_vec();
+        inputs.push(1.0);
+        let hidden_sums: Vec<f32> = self.hidden_layer.iter()
+            .map(|n| n.sum(&inputs))
+            .collect();
+        let mut hidden: Vec<f32> = self.hidden_layer.iter().zip(hidden_sums.iter())
+            .map(|(n, sum)| n.activation.apply(*sum))
+            .collect();
+        hidden.push(1.0);
+        let output_sums: Vec<f32> = self.output_layer.iter()
+            .map(|n| n.sum(&hidden))
+            .collect();
+        let outputs: Vec<f32> = self.output_layer.iter().zip(output_sums.iter())
+            .map(|(n, sum)| n.activation.apply(*sum))
+            .collect();
+
+        let output_slopes = loss_gradient(&outputs);
+        let mut hidden_slopes = vec![0.0; self.hidden_layer.len()];
+        for (i, neuron) in self.output_layer.iter().enumerate() {
+            let delta = output_slopes[i] * neuron.activation.derivative(output_sums[i]);
+            for (j, weight) in neuron.weights.iter().enumerate() {
+                gradients.output[i][j] += delta * hidden[j];
+                // The bias has no neuron behind it.
+                if let Some(slope) = hidden_slopes.get_mut(j) {
+                    *slope += delta * weight;
+                }
+            }
+        }
+        for (j, neuron) in self.hidden_layer.iter().enumerate() {
+            let delta = hidden_slopes[j] * neuron.activation.derivative(hidden_sums[j]);
+            for (k, input) in inputs.iter().enumerate() {
+                gradients.hidden[j][k] += delta * input;
+            }
+        }
+        outputs
+    }
+
+    /// Moves weights against the gradients.
+    /// Unconnected synapses stay unconnected,
+    /// so that the evolved shape of the brain is kept.
+    pub fn descend(&mut self, gradients: &Gradients, rate: f32) {
+        let descend_layer = |layer: &mut [Neuron], gradients: &[Vec<f32>]| {
+            for (neuron, gradients) in layer.iter_mut().zip(gradients) {
+                for (weight, gradient) in neuron.weights.iter_mut().zip(gradients) {
+                    if *weight != UNCONNECTED {
+                        *weight -= rate * gradient;
+                    }
+                }
+            }
+        };
+        descend_layer(&mut self.hidden_layer, &gradients.hidden);
+        descend_layer(&mut self.output_layer, &gradients.output);
     }
 
     pub fn get_layers(&self) -> Vec<Vec<NodeId>> {
@@ -282,14 +401,20 @@ impl brain::Brain for Brain {
     type Inputs = Inputs;
     type Outputs = Outputs;
     fn process(&mut self, inputs: Inputs) -> Outputs {
-        let inputs = Brain::normalize_inputs(inputs);
-        let hidden = process_layer(&self.hidden_layer, inputs);
-        let outputs = process_layer(&self.output_layer, hidden);
-        Outputs {
-            walk: outputs[2],
-            turn: outputs[1],
-            shoot: true,
-            aim_rel_angle: outputs[0],
+        self.process_buffered(inputs, &mut Default::default())
+    }
+
+    fn process_batch<B, I>(
+        batch: I,
+        buffers: &mut brain::Buffers,
+        outputs: &mut Vec<Outputs>,
+    )
+        where B: DerefMut<Target=Brain>,
+            I: IntoIterator<Item=(B, Inputs)>,
+    {
+        outputs.clear();
+        for (brain, inputs) in batch {
+            outputs.push(brain.process_buffered(inputs, buffers));
         }
     }
 
@@ -376,22 +501,80 @@ impl brain::MixableGenotype for Brain {
     }
 }
 
+/// Slopes of the loss over each weight, shaped like the brain.
+#[derive(Debug, Clone)]
+pub struct Gradients {
+    hidden: Vec<Vec<f32>>,
+    output: Vec<Vec<f32>>,
+}
+
+impl Gradients {
+    /// All zero, for the given brain.
+    pub fn new(brain: &Brain) -> Gradients {
+        let zeros = |layer: &[Neuron]| {
+            layer.iter().map(|n| vec![0.0; n.weights.len()]).collect()
+        };
+        Gradients {
+            hidden: zeros(&brain.hidden_layer),
+            output: zeros(&brain.output_layer),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        for weights in self.hidden.iter_mut().chain(self.output.iter_mut()) {
+            for w in weights.iter_mut() {
+                *w = 0.0;
+            }
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Inputs {
     //mob_distance: f32,
     mob_rel_angle: f32,
     time_survived: f32,
+    /// 1 when walking into an obstacle, down to 0 when it's out of sight
+    obstacle_ahead: f32,
 }
 
-const INPUT_COUNT: u8 = 2;
+/// Obstacles farther than this in the walking direction can't be seen.
+const SIGHT_RANGE: f32 = 100.0;
+
+impl Inputs {
+    /// What a borg at `position` knows about the world.
+    pub fn sense(
+        position: &Isometry<f32>,
+        time_alive: f32,
+        mob_positions: &[Point2<f32>],
+        obstacles: &[Shape],
+        topology: Topology,
+    ) -> Inputs {
+        let origin = position.translation.vector.into();
+        let nearest = get_nearest(&origin, mob_positions, topology)
+            .unwrap_or(Point2::new(0.0, 0.0));
+        let rot = angle_from(position, &nearest, topology);
+        let ahead = position.rotation.transform_vector(&Vector2::y());
+        let obstacle_distance = ray_distance(obstacles, &origin, &ahead)
+            .unwrap_or(SIGHT_RANGE)
+            .min(SIGHT_RANGE);
+        Inputs {
+            mob_rel_angle: rot / f32::consts::PI,
+            time_survived: time_alive,
+            obstacle_ahead: 1.0 - obstacle_distance / SIGHT_RANGE,
+        }
+    }
+}
+
+pub const INPUT_COUNT: u8 = 3;
 
 pub struct Outputs {
-    walk: f32,
+    pub walk: f32,
     /// Relative to walking direction
-    turn: f32,
-    shoot: bool,
+    pub turn: f32,
+    pub shoot: bool,
     /// Relative to walking direction
-    aim_rel_angle: f32,
+    pub aim_rel_angle: f32,
 }
 
 
@@ -400,15 +583,25 @@ pub struct BrainFed {
     pub inputs: Inputs,
 }
 
+/// Memory reused between think rounds.
+#[derive(Default)]
+pub struct ThinkState {
+    buffers: brain::Buffers,
+    inputs: Vec<Inputs>,
+    outputs: Vec<Outputs>,
+}
+
 pub fn think(
     mut commands: &mut Commands,
+    mut state: Local<ThinkState>,
     mut brain_fed_events: ResMut<Events<BrainFed>>,
     asset_server: Res<AssetServer>,
     assets: Res<assets::Assets>,
     audio_output: Res<Audio>,
+    runstate: Res<RunState>,
     mut bodies: ResMut<RigidBodySet>,
     mobs: Query<(&RigidBodyHandleComponent, &Mob)>,
-    mut borgs: Query<(Entity, &RigidBodyHandleComponent, &Borg, Mut<Brain>)>,
+    mut borgs: Query<(Entity, &RigidBodyHandleComponent, Mut<Borg>, Mut<Brain>, Option<Mut<novelty::Behaviour>>)>,
     mut weapons: Query<(Mut<Weapon>, Mut<Transform>, &AttachedToEntity), Without<LooksAt>>,
 ) {
     let mob_positions: Vec<_>
@@ -417,18 +610,38 @@ pub fn think(
         .map(|body| body.position().translation.vector.clone().into())
         .collect();
 
-    for (entity, body, borg, mut brain) in borgs.iter_mut() {
-        let mut body = bodies.get_mut(body.handle()).unwrap();
-        let nearest = get_nearest(&body.position().translation.vector.into(), &mob_positions)
-            .unwrap_or(Point2::new(0.0, 0.0));
-        let rot = angle_from(body.position(), &nearest);
-        let inputs = Inputs {
-            mob_rel_angle: rot / f32::consts::PI,
-            time_survived: borg.time_alive,
-        };
+    let obstacles = runstate.arena.as_ref()
+        .map(|arena| arena.obstacles.as_slice())
+        .unwrap_or(&[]);
+
+    state.inputs.clear();
+    for (entity, body, borg, _brain, _behaviour) in borgs.iter_mut() {
+        let body = bodies.get(body.handle()).unwrap();
+        let inputs = Inputs::sense(
+            body.position(),
+            borg.time_alive,
+            &mob_positions,
+            obstacles,
+            runstate.topology,
+        );
         brain_fed_events.send(BrainFed { entity, inputs: inputs.clone() });
-        let outputs = brain.process(inputs);
-        // Apply outputs. Might be better to do this in a separate step.
+        state.inputs.push(inputs);
+    }
+
+    {
+        let ThinkState { buffers, inputs, outputs } = &mut *state;
+        Brain::process_batch(
+            borgs.iter_mut()
+                .map(|(_e, _b, _borg, brain, _behaviour)| brain)
+                .zip(inputs.drain(..)),
+            buffers,
+            outputs,
+        );
+    }
+
+    // Apply outputs. Query order is stable as long as nothing gets spawned in between.
+    for ((entity, body, mut borg, _brain, mut behaviour), outputs) in borgs.iter_mut().zip(state.outputs.iter()) {
+        let body = bodies.get_mut(body.handle()).unwrap();
         body.set_angvel(
             (outputs.turn * borg.rotation_speed).min(borg.rotation_speed).max(-borg.rotation_speed),
             true,
@@ -440,27 +653,234 @@ pub fn think(
             )),
             true,
         );
+        let abs_angle = body.position().rotation.angle() + outputs.aim_rel_angle.max(-1.0).min(1.0) * f32::consts::PI;
+        let position = body.position().translation.vector;
+        if let Some(behaviour) = behaviour.as_mut() {
+            behaviour.sample(position.x, position.y, abs_angle);
+        }
         let weapons = weapons.iter_mut().filter(|(_w, _t, parent)| parent.0 == entity);
         for (mut weapon, mut transform, _parent) in weapons {
-            let abs_angle = body.position().rotation.angle() + outputs.aim_rel_angle.max(-1.0).min(1.0) * f32::consts::PI;
             transform.rotation = Quat::from_axis_angle(Vec3::new(0.0, 0.0, 1.0), abs_angle);
+            // `follow` only catches up once per frame, and a frame can hold many ticks.
+            transform.translation.x = position.x;
+            transform.translation.y = position.y;
             if outputs.shoot {
-                weapon_trigger(&mut weapon, &transform, &mut commands, &asset_server, &assets, &audio_output);
+                borg.shots += weapon_trigger(&mut weapon, &transform, entity, &mut commands, &asset_server, &assets, &audio_output);
             }
         }
     }
 }
 
 
-pub type Genotype = Brain;
+/// Sum of trait ratios allowed to a body. The default body uses exactly that.
+const BODY_BUDGET: f32 = 5.0;
 
-/*
-#[derive(Clone)]
+/// Physical traits of the borg and its weapon.
+/// Missing traits in files are taken from the default body.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Body {
+    /// Max movement speed
+    pub speed: f32,
+    /// Max rotation speed in rad/s
+    pub rotation_speed: f32,
+    /// Seconds between shots
+    pub fire_interval: f32,
+    pub projectile_speed: f32,
+    /// Seconds before the projectile disappears
+    pub projectile_lifetime: f32,
+}
+
+impl Default for Body {
+    fn default() -> Body {
+        Body {
+            speed: 30.0,
+            rotation_speed: f32::consts::TAU,
+            fire_interval: 0.5,
+            projectile_speed: 200.0,
+            projectile_lifetime: 5.0,
+        }
+    }
+}
+
+impl Body {
+    /// How much better than default each trait is.
+    /// Fire interval is better when shorter.
+    fn ratios(&self) -> [f32; 5] {
+        let d = Body::default();
+        [
+            self.speed / d.speed,
+            self.rotation_speed / d.rotation_speed,
+            d.fire_interval / self.fire_interval,
+            self.projectile_speed / d.projectile_speed,
+            self.projectile_lifetime / d.projectile_lifetime,
+        ]
+    }
+
+    fn from_ratios(ratios: [f32; 5]) -> Body {
+        let d = Body::default();
+        Body {
+            speed: d.speed * ratios[0],
+            rotation_speed: d.rotation_speed * ratios[1],
+            fire_interval: d.fire_interval / ratios[2],
+            projectile_speed: d.projectile_speed * ratios[3],
+            projectile_lifetime: d.projectile_lifetime * ratios[4],
+        }
+    }
+
+    pub fn cost(&self) -> f32 {
+        self.ratios().iter().sum()
+    }
+
+    /// Scales all traits down evenly if the body is over the budget.
+    fn fit_budget(self) -> Body {
+        let cost = self.cost();
+        if cost <= BODY_BUDGET {
+            return self;
+        }
+        let mut ratios = self.ratios();
+        for ratio in ratios.iter_mut() {
+            *ratio *= BODY_BUDGET / cost;
+        }
+        Body::from_ratios(ratios)
+    }
+
+    fn mutate(&self, strength: f64) -> Body {
+        let mut rng = rand::thread_rng();
+        let mut ratios = self.ratios();
+        for ratio in ratios.iter_mut() {
+            let change: f32 = rng.sample::<f32, _>(StandardNormal) * strength as f32;
+            *ratio = (*ratio * change.exp()).max(0.1);
+        }
+        Body::from_ratios(ratios).fit_budget()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Genotype {
-    brain: Brain,
-    /// Tracking "true" generation number
-    mutation_rounds: u16,
-}*/
+    pub brain: Brain,
+    #[serde(default)]
+    pub body: Body,
+    #[serde(default)]
+    pub weapon: weapon::Kind,
+}
+
+impl Genotype {
+    pub fn pretty_print(&self) -> Result<String, fmt::Error> {
+        let mut f = self.brain.pretty_print()?;
+        let b = &self.body;
+        writeln!(
+            f,
+            "Body: speed {:.1} rotation {:.2} fire interval {:.2} projectile speed {:.1} lifetime {:.2}",
+            b.speed, b.rotation_speed, b.fire_interval, b.projectile_speed, b.projectile_lifetime,
+        )?;
+        writeln!(f, "Weapon: {:?}", self.weapon)?;
+        Ok(f)
+    }
+
+    fn mutate(self, strength: f64) -> Genotype {
+        let mut rng = rand::thread_rng();
+        let switch = Bernoulli::new(strength * 0.1).unwrap();
+        Genotype {
+            body: self.body.mutate(strength),
+            brain: self.brain.mutate(strength),
+            weapon: match switch.sample(&mut rng) {
+                true => weapon::Kind::random(&mut rng),
+                false => self.weapon,
+            },
+        }
+    }
+}
+
+/// A genotype which got its chance in the arena.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub genotype: Genotype,
+    /// Decides the spawn rate
+    pub fitness: f64,
+    pub id: u64,
+    /// What the fitness was made of
+    pub breakdown: fitness::Breakdown,
+    pub record: fitness::Record,
+    /// Behaviour descriptor. Empty if never tested.
+    pub behaviour: Vec<f64>,
+    /// Updated as the pool changes
+    pub novelty: f64,
+    /// Curriculum level the genotype was tested at
+    pub level: usize,
+}
+
+impl Entry {
+    fn new_blank(genotype: Genotype, fitness: f64) -> Entry {
+        Entry {
+            genotype,
+            fitness,
+            id: 0,
+            breakdown: Vec::new(),
+            record: Default::default(),
+            behaviour: Vec::new(),
+            novelty: 0.0,
+            level: 0,
+        }
+    }
+
+    /// Separate measures of success used for Pareto selection:
+    /// kills, survival, accuracy, distance travelled.
+    pub fn objectives(&self) -> Vec<f64> {
+        let r = &self.record;
+        vec![
+            r.kill_score as f64,
+            r.time_alive as f64,
+            match r.shots {
+                0 => 0.0,
+                shots => r.hits as f64 / shots as f64,
+            },
+            r.distance as f64,
+        ]
+    }
+}
+
+/// Pool contents as stored on disk.
+#[derive(Serialize, Deserialize)]
+struct PoolFile {
+    /// Id, fitness, genotype
+    genotypes: Vec<(u64, f64, Genotype)>,
+    /// Id, what the fitness was made of
+    #[serde(default)]
+    breakdowns: Vec<(u64, Vec<(String, f64)>)>,
+    /// Id, curriculum level the genotype was tested at
+    #[serde(default)]
+    levels: Vec<(u64, usize)>,
+}
+
+/// Loads the genotype with the given id from a saved pool,
+/// or the fittest one if no id is given.
+pub fn load_genotype(path: &Path, id: Option<u64>) -> Result<(u64, Genotype), anyhow::Error> {
+    let mut pool: PoolFile = ron::de::from_reader(File::open(path)?)?;
+    for (_id, _fitness, genotype) in pool.genotypes.iter_mut() {
+        genotype.brain.fit_inputs();
+    }
+    let found = match id {
+        Some(id) => pool.genotypes.into_iter().find(|(i, _, _)| *i == id),
+        None => pool.genotypes.into_iter()
+            .max_by(|(_, f0, _), (_, f1, _)| f0.partial_cmp(f1).unwrap_or(Ordering::Equal)),
+    };
+    found.map(|(id, _fitness, genotype)| (id, genotype))
+        .ok_or_else(|| anyhow!("No such genotype in {:?}", path))
+}
+
+/// How parents get chosen, and who gets removed from the pool.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Selection {
+    /// Spawn rate follows fitness, oldies get removed.
+    Weighted,
+    /// NSGA-II-style. Keeps Pareto fronts over `Entry::objectives`,
+    /// breaking ties with crowding distance. Fitness is ignored.
+    Pareto,
+    /// Spawn rate follows novelty of behaviour,
+    /// plus fitness multiplied by the weight.
+    Novelty { fitness_weight: f64 },
+}
 
 /// Third iteration.
 /// Let's experiment with keeping Adam and Eve as a regular genotype,
@@ -470,26 +890,136 @@ pub struct Genotype {
 /// That becomes the new generation size.
 #[derive(Debug)]
 pub struct GenePool {
-    /// Mapping: breeding genotype, spawn rate
+    /// Breeding genotypes.
     /// Spawn rate should be derived from objective success
-    /// In this case, it's seconds of survival
-    genotypes: Vec<(Genotype, f64, u64)>,
+    genotypes: Vec<Entry>,
     preserved_total: u64,
+    selection: Selection,
+    archive: novelty::Archive,
 }
 
 impl GenePool {
-    pub fn new_eden() -> GenePool {
+    pub fn new_eden(selection: Selection) -> GenePool {
         GenePool {
             genotypes: vec![
                 // Let it be the main source of breeding
                 // until reaching ideal population's fraction.
                 // Expected total kills at population ten: 20. Be better than that.
-                (Brain::new_dumb(3), 40.0 * 20.0, 0),
+                Entry::new_blank(
+                    Genotype {
+                        brain: Brain::new_dumb(3),
+                        body: Body::default(),
+                        weapon: weapon::Kind::Laser,
+                    },
+                    40.0 * 20.0,
+                ),
             ],
             preserved_total: 1,
+            selection,
+            archive: novelty::Archive::new(),
         }
     }
 
+    /// Adds a genotype which didn't come from the arena, like an imitation of a human.
+    /// It breeds as much as the original blank one at first.
+    pub fn seed(&mut self, genotype: Genotype) {
+        let mut entry = Entry::new_blank(genotype, 40.0 * 20.0);
+        entry.id = self.preserved_total;
+        println!("Seeded as {}", entry.id);
+        self.genotypes.push(entry);
+        self.preserved_total += 1;
+    }
+
+    /// Only novelty search looks at how borgs behaved.
+    pub fn needs_behaviour(&self) -> bool {
+        match self.selection {
+            Selection::Novelty { .. } => true,
+            Selection::Weighted | Selection::Pareto => false,
+        }
+    }
+
+    fn spawn_novel(&self, fitness_weight: f64) -> Genotype {
+        // Novelty is a distance between fractions mostly, so scale it up to score level.
+        let distribution = WeightedIndex::new(
+            self.genotypes.iter()
+                .map(|e| 1000.0 * e.novelty + fitness_weight * e.fitness + 40.0)
+        ).unwrap();
+        let entry = &self.genotypes[distribution.sample(&mut rand::thread_rng())];
+        println!("Spawn offspring of {} (novelty {:.3})", entry.id, entry.novelty);
+        GenePool::mutate(entry.genotype.clone(), self.get_mut_rate(), 0.12)
+    }
+
+    /// Novelty of every entry changes whenever the pool does.
+    fn update_novelty(&mut self) {
+        let novelties: Vec<f64> = {
+            let population: Vec<&[f64]> = self.genotypes.iter()
+                .map(|e| e.behaviour.as_slice())
+                .filter(|b| !b.is_empty())
+                .collect();
+            self.genotypes.iter()
+                .map(|e| match e.behaviour.is_empty() {
+                    true => 0.0,
+                    false => self.archive.novelty(&e.behaviour, &population),
+                })
+                .collect()
+        };
+        for (entry, novelty) in self.genotypes.iter_mut().zip(novelties) {
+            entry.novelty = novelty;
+        }
+    }
+
+    fn to_file(&self) -> PoolFile {
+        PoolFile {
+            genotypes: self.genotypes.iter()
+                .map(|e| (e.id, e.fitness, e.genotype.clone()))
+                .collect(),
+            breakdowns: self.genotypes.iter()
+                .map(|e| (
+                    e.id,
+                    e.breakdown.iter()
+                        .map(|(name, value)| (name.to_string(), *value))
+                        .collect(),
+                ))
+                .collect(),
+            levels: self.genotypes.iter()
+                .map(|e| (e.id, e.level))
+                .collect(),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), anyhow::Error> {
+        let contents = ron::ser::to_string_pretty(&self.to_file(), Default::default())?;
+        fs::write(path, contents)?;
+        Ok(())
+    }
+
+    fn objectives(&self) -> Vec<Vec<f64>> {
+        self.genotypes.iter().map(Entry::objectives).collect()
+    }
+
+    /// The current Pareto front: entries not dominated by any other.
+    pub fn front(&self) -> Vec<&Entry> {
+        pareto::sort_fronts(&self.objectives())
+            .first()
+            .map(|front| front.iter().map(|i| &self.genotypes[*i]).collect())
+            .unwrap_or_default()
+    }
+
+    /// Binary tournament on Pareto rank.
+    fn spawn_pareto(&self) -> Genotype {
+        let ranks = pareto::rank(&self.objectives());
+        let mut rng = rand::thread_rng();
+        let a = rng.gen_range(0, self.genotypes.len());
+        let b = rng.gen_range(0, self.genotypes.len());
+        let winner = match ranks[b].better_than(&ranks[a]) {
+            true => b,
+            false => a,
+        };
+        let entry = &self.genotypes[winner];
+        println!("Spawn offspring of {}", entry.id);
+        GenePool::mutate(entry.genotype.clone(), self.get_mut_rate(), 0.12)
+    }
+
     fn mutate(g: Genotype, times: u8, strength: f64) -> Genotype {
         if times == 0 {
             g
@@ -501,12 +1031,12 @@ impl GenePool {
     fn spawn_sexless(&self) -> Genotype {
         // Give them a chance to reflect their fitness.
         let distribution = WeightedIndex::new(
-            self.genotypes.iter().map(|(_k, v, _id)| v + 40.0)
+            self.genotypes.iter().map(|e| e.fitness + 40.0)
         ).unwrap();
         let index = distribution.sample(&mut rand::thread_rng());
         let (genotype, id) = self.genotypes
             .get(index)
-            .map(|(genotype, _, id)| (genotype.clone(), id))
+            .map(|e| (e.genotype.clone(), e.id))
             .unwrap();
         println!("Spawn offspring of {}", id);
         GenePool::mutate(genotype, self.get_mut_rate(), 0.12)
@@ -521,30 +1051,86 @@ impl GenePool {
     /// Spawn hermaphoditic
     fn spawn_herm(&self) -> Genotype {
         let distribution = WeightedIndex::new(
-            self.genotypes.iter().map(|(_k, v, _id)| v + 40.0)
+            self.genotypes.iter().map(|e| e.fitness + 40.0)
         ).unwrap();
         let index0 = distribution.sample(&mut rand::thread_rng());
         let index1 = distribution.sample(&mut rand::thread_rng());
         
-        let (genotype0, _w, id0) = self.genotypes.get(index0).unwrap();
-        let (genotype1, _w, id1) = self.genotypes.get(index1).unwrap();
-        println!("Spawn offspring of {} and {}", id0, id1);
+        let e0 = self.genotypes.get(index0).unwrap();
+        let e1 = self.genotypes.get(index1).unwrap();
+        println!("Spawn offspring of {} and {}", e0.id, e1.id);
         // Mutation rate shouldn't be too big;
         // there's enough mess due to sexual reproduction.
-        GenePool::mutate(genotype0.mix_with(genotype1), self.get_mut_rate(), 0.06)
+        let genotype = Genotype {
+            brain: e0.genotype.brain.mix_with(&e1.genotype.brain),
+            ..e0.genotype.clone()
+        };
+        GenePool::mutate(genotype, self.get_mut_rate(), 0.06)
     }
 
     pub fn spawn(&self) -> Genotype {
-        self.spawn_sexless()
+        match self.selection {
+            Selection::Weighted => self.spawn_sexless(),
+            Selection::Pareto => self.spawn_pareto(),
+            Selection::Novelty { fitness_weight } => self.spawn_novel(fitness_weight),
+        }
     }
 
-    pub fn preserve(&mut self, genotype: Genotype, fitness: f64) {
-        self.genotypes.push((genotype, fitness, self.preserved_total));
-        println!("Preserved as {} with score {}", self.preserved_total, fitness);
+    /// Returns the id given to the genotype.
+    pub fn preserve(
+        &mut self,
+        genotype: Genotype,
+        record: fitness::Record,
+        fitness: f64,
+        breakdown: fitness::Breakdown,
+        behaviour: Vec<f64>,
+        level: usize,
+    ) -> u64 {
+        let id = self.preserved_total;
+        print!("Preserved as {} with score {} at level {}", self.preserved_total, fitness, level);
+        for (name, value) in &breakdown {
+            print!(" {}: {:.1}", name, value);
+        }
+        println!();
+        self.genotypes.push(Entry {
+            genotype,
+            fitness,
+            id: self.preserved_total,
+            breakdown,
+            record,
+            behaviour,
+            novelty: 0.0,
+            level,
+        });
         println!("Pop {}", self.genotypes.len());
         self.preserved_total += 1;
+
+        if self.needs_behaviour() {
+            self.update_novelty();
+            if let Some(entry) = self.genotypes.last() {
+                if !entry.behaviour.is_empty() {
+                    println!("Novelty {:.3}, archived {}", entry.novelty, self.archive.len());
+                    self.archive.consider(&entry.behaviour, entry.novelty);
+                }
+            }
+        }
         
         let ideal_pop_size = 20;
+        if self.selection == Selection::Pareto {
+            if self.genotypes.len() > ideal_pop_size {
+                let mut keep = pareto::select(&self.objectives(), ideal_pop_size);
+                keep.sort();
+                let old = mem::replace(&mut self.genotypes, Vec::new());
+                self.genotypes = old.into_iter().enumerate()
+                    .filter(|(i, _)| keep.binary_search(i).is_ok())
+                    .map(|(_, e)| e)
+                    .collect();
+            }
+            let front: Vec<_> = self.front().iter().map(|e| e.id).collect();
+            println!("Pareto front {:?}", front);
+            return id;
+        }
+
         let minimal_pop_size = ideal_pop_size / 4;
         let mut rng = rand::thread_rng();
         
@@ -563,10 +1149,21 @@ impl GenePool {
             println!("Killing {} oldies. Now pop {}.", kill_count, new.len());
             if new.len() < minimal_pop_size {
                 println!("Filling up to {} with blanks", minimal_pop_size);
-                new.resize(minimal_pop_size, (Brain::new_dumb(3), 40.0, 0));
+                new.resize(
+                    minimal_pop_size,
+                    Entry::new_blank(
+                        Genotype {
+                            brain: Brain::new_dumb(3),
+                            body: Default::default(),
+                            weapon: weapon::Kind::Laser,
+                        },
+                        40.0,
+                    ),
+                );
             }
             self.genotypes = new;
         }
+        id
     }
 }
 
@@ -580,7 +1177,157 @@ mod tests {
         let signals = brain.find_signals(Inputs {
             mob_rel_angle: 0.0,
             time_survived: 0.0,
+            obstacle_ahead: 0.0,
         });
         //assert_eq!(signals, vec![]);
     }
+
+    #[test]
+    fn old_brains_fit() {
+        let mut brain = Brain::new_dumb(3);
+        for neuron in brain.hidden_layer.iter_mut() {
+            neuron.weights = vec![0.5, 0.25, 1.0];
+        }
+        brain.fit_inputs();
+        for neuron in &brain.hidden_layer {
+            assert_eq!(neuron.weights, vec![0.5, 0.25, UNCONNECTED, 1.0]);
+        }
+    }
+
+    #[test]
+    fn body_within_budget() {
+        let body = Body {
+            speed: 60.0,
+            ..Body::default()
+        }.fit_budget();
+        assert!((body.cost() - BODY_BUDGET).abs() < 0.001);
+        assert!(body.speed < 60.0);
+        assert!(body.fire_interval > Body::default().fire_interval);
+    }
+
+    /// Backprop should agree with nudging a weight and seeing what happens.
+    #[test]
+    fn gradients_match_numeric() {
+        let mut brain = Brain::new_dumb(3);
+        for (i, neuron) in brain.hidden_layer.iter_mut().chain(brain.output_layer.iter_mut()).enumerate() {
+            neuron.activation = [Function::Linear, Function::Logistic, Function::Tanh][i % 3].clone();
+            for (j, weight) in neuron.weights.iter_mut().enumerate() {
+                *weight = 0.1 * (i + j) as f32 - 0.3;
+            }
+        }
+        let inputs = [0.3, 1.5, 0.2];
+        let loss = |brain: &Brain| {
+            let mut inputs = inputs.to_vec();
+            let mut hidden = Vec::new();
+            let mut outputs = Vec::new();
+            process_layer(&brain.hidden_layer, &mut inputs, &mut hidden);
+            process_layer(&brain.output_layer, &mut hidden, &mut outputs);
+            outputs.iter().map(|o| 0.5 * o * o).sum::<f32>()
+        };
+        let mut gradients = Gradients::new(&brain);
+        brain.backprop(&inputs, |outputs| outputs.to_vec(), &mut gradients);
+
+        let epsilon = 0.001;
+        let mut nudged = brain.clone();
+        nudged.hidden_layer[1].weights[0] += epsilon;
+        let numeric = (loss(&nudged) - loss(&brain)) / epsilon;
+        assert!((numeric - gradients.hidden[1][0]).abs() < 0.01, "{} {}", numeric, gradients.hidden[1][0]);
+
+        let mut nudged = brain.clone();
+        nudged.output_layer[2].weights[1] += epsilon;
+        let numeric = (loss(&nudged) - loss(&brain)) / epsilon;
+        assert!((numeric - gradients.output[2][1]).abs() < 0.01, "{} {}", numeric, gradients.output[2][1]);
+    }
+
+    #[test]
+    fn batch_matches_single() {
+        let mut brains = vec![
+            Brain::new_dumb(3).mutate(1.0),
+            Brain::new_dumb(3).mutate(1.0),
+        ];
+        let inputs = |i| Inputs {
+            mob_rel_angle: 0.5 * i as f32,
+            time_survived: 3.0,
+            obstacle_ahead: 0.1 * i as f32,
+        };
+        let single: Vec<_> = brains.iter_mut().enumerate()
+            .map(|(i, b)| b.process(inputs(i)).aim_rel_angle)
+            .collect();
+        let mut outputs = Vec::new();
+        Brain::process_batch(
+            brains.iter_mut().enumerate().map(|(i, b)| (b, inputs(i))),
+            &mut Default::default(),
+            &mut outputs,
+        );
+        let batched: Vec<_> = outputs.iter().map(|o| o.aim_rel_angle).collect();
+        assert_eq!(single, batched);
+    }
+
+    #[test]
+    fn archive_only_for_novelty() {
+        let preserve = |pool: &mut GenePool| {
+            pool.preserve(
+                Genotype {
+                    brain: Brain::new_dumb(3),
+                    body: Body::default(),
+                    weapon: weapon::Kind::Laser,
+                },
+                Default::default(),
+                10.0,
+                Vec::new(),
+                vec![0.5, 0.5],
+                0,
+            );
+        };
+        let mut weighted = GenePool::new_eden(Selection::Weighted);
+        preserve(&mut weighted);
+        assert_eq!(weighted.archive.len(), 0);
+        let mut novelty = GenePool::new_eden(Selection::Novelty { fitness_weight: 0.0 });
+        preserve(&mut novelty);
+        assert_eq!(novelty.archive.len(), 1);
+    }
+
+    #[test]
+    fn breakdown_saved() {
+        let mut pool = GenePool::new_eden(Selection::Weighted);
+        let id = pool.preserve(
+            Genotype {
+                brain: Brain::new_dumb(3),
+                body: Body::default(),
+                weapon: weapon::Kind::Laser,
+            },
+            Default::default(),
+            30.0,
+            vec![("kills", 20.0), ("survival", 10.0)],
+            Vec::new(),
+            0,
+        );
+        let contents = ron::ser::to_string(&pool.to_file()).unwrap();
+        let loaded: PoolFile = ron::de::from_str(&contents).unwrap();
+        let (_id, breakdown) = loaded.breakdowns.iter().find(|(i, _)| *i == id).unwrap();
+        assert_eq!(breakdown, &vec![("kills".to_string(), 20.0), ("survival".to_string(), 10.0)]);
+    }
+
+    #[test]
+    fn level_saved() {
+        let mut pool = GenePool::new_eden(Selection::Weighted);
+        let id = pool.preserve(
+            Genotype {
+                brain: Brain::new_dumb(3),
+                body: Body::default(),
+                weapon: weapon::Kind::Laser,
+            },
+            Default::default(),
+            30.0,
+            Vec::new(),
+            Vec::new(),
+            2,
+        );
+        let contents = ron::ser::to_string(&pool.to_file()).unwrap();
+        let loaded: PoolFile = ron::de::from_str(&contents).unwrap();
+        assert!(loaded.levels.contains(&(id, 2)));
+        // Pools saved before levels were recorded still load.
+        let old: PoolFile = ron::de::from_str("(genotypes: [])").unwrap();
+        assert!(old.levels.is_empty());
+    }
 }
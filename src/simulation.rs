@@ -0,0 +1,280 @@
+/*! Simulated time.
+ * The arena advances in fixed ticks, and in AI rounds,
+ * several ticks can run within a single rendered frame. */
+
+/*
+ Author: Dorota Czaplejewicz <gihuac.dcz@porcupinefactory.org>
+ SPDX-License-Identifier: AGPL-3.0-or-later
+ */
+
+use bevy::ecs::{ Res, ResMut, ShouldRun };
+use bevy_rapier2d::physics::{ EntityMaps, EventQueue, InteractionPairFilters, RapierConfiguration };
+use bevy_rapier2d::rapier::{
+    dynamics::{ IntegrationParameters, JointSet, RigidBodySet },
+    geometry::{ BroadPhase, ColliderSet, NarrowPhase },
+    pipeline::PhysicsPipeline,
+};
+use std::time::{ Duration, Instant };
+use super::input_map::{ Action, Actions };
+use super::state::{ GameState, Mode, RunState };
+
+
+/// Seconds of simulated time in one tick. Same as Rapier's default step.
+pub const TICK: f32 = 1.0 / 60.0;
+
+/// At max speed, ticks keep running until the frame took this long.
+const MAX_FRAME_TIME: Duration = Duration::from_millis(30);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Speed {
+    X1,
+    X2,
+    X4,
+    X16,
+    /// As many ticks as fit in a frame
+    Max,
+}
+
+impl Speed {
+    const ALL: [Speed; 5] = [Speed::X1, Speed::X2, Speed::X4, Speed::X16, Speed::Max];
+
+    fn index(self) -> usize {
+        Speed::ALL.iter().position(|s| *s == self).unwrap()
+    }
+
+    pub fn faster(self) -> Speed {
+        Speed::ALL[(self.index() + 1).min(Speed::ALL.len() - 1)]
+    }
+
+    pub fn slower(self) -> Speed {
+        Speed::ALL[self.index().saturating_sub(1)]
+    }
+
+    /// None means as many as fit.
+    pub fn ticks_per_frame(self) -> Option<u32> {
+        match self {
+            Speed::X1 => Some(1),
+            Speed::X2 => Some(2),
+            Speed::X4 => Some(4),
+            Speed::X16 => Some(16),
+            Speed::Max => None,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Speed::X1 => "1x",
+            Speed::X2 => "2x",
+            Speed::X4 => "4x",
+            Speed::X16 => "16x",
+            Speed::Max => "max",
+        }
+    }
+}
+
+/// Only applies to rounds without a player.
+#[derive(Debug)]
+pub struct Clock {
+    pub speed: Speed,
+    /// No ticks, except when stepping
+    pub frozen: bool,
+    /// Run one tick in the next frame while frozen
+    step: bool,
+    /// Ticks run in the current frame
+    ticks: u32,
+    frame_start: Instant,
+}
+
+impl Default for Clock {
+    fn default() -> Clock {
+        Clock {
+            speed: Speed::X1,
+            frozen: false,
+            step: false,
+            ticks: 0,
+            frame_start: Instant::now(),
+        }
+    }
+}
+
+impl Clock {
+    /// For the score line. Empty at normal speed.
+    pub fn label(&self) -> String {
+        match (self.frozen, self.speed) {
+            (true, _) => "paused".into(),
+            (false, Speed::X1) => String::new(),
+            (false, speed) => speed.label().into(),
+        }
+    }
+
+    /// Decides if another tick should run in this frame.
+    /// Once it says no, the next call starts a new frame.
+    /// `speed` of None means frozen.
+    fn next_tick(&mut self, speed: Option<Speed>, now: Instant) -> bool {
+        if self.ticks == 0 {
+            self.frame_start = now;
+        }
+        let more = match speed {
+            None => self.ticks == 0 && std::mem::replace(&mut self.step, false),
+            Some(speed) => match speed.ticks_per_frame() {
+                Some(count) => self.ticks < count,
+                None => self.ticks == 0 || now - self.frame_start < MAX_FRAME_TIME,
+            },
+        };
+        match more {
+            true => self.ticks += 1,
+            false => self.ticks = 0,
+        }
+        more
+    }
+}
+
+/// Run criteria for the simulation stage.
+/// Player rounds always get one tick per frame, and nothing moves during the pause menu.
+pub fn tick(mut clock: ResMut<Clock>, runstate: Res<RunState>) -> ShouldRun {
+    let state = runstate.gamestate.current();
+    // An unattended round stops dead once it's over,
+    // or the next one would depend on how many ticks the last frame still fit.
+    let round_over = match state {
+        GameState::Arena(mode) => !mode.has_player() && runstate.gamestate.in_transition(),
+        GameState::ArenaOver(mode) | GameState::BetweenRounds(mode) => !mode.has_player(),
+        _ => false,
+    };
+    // Nor does it start before it's entered.
+    // The first round doesn't come from between rounds.
+    let round_starting = runstate.gamestate.entering()
+        .and_then(|state| state.arena_mode())
+        .map(|mode| !mode.has_player())
+        .unwrap_or(false);
+    if round_over || round_starting {
+        clock.ticks = 0;
+        return ShouldRun::No;
+    }
+    let speed = match state {
+        GameState::ArenaPause(_) => None,
+        _ => match state.arena_mode() {
+            Some(mode) if !mode.has_player() => match clock.frozen {
+                true => None,
+                false => Some(clock.speed),
+            },
+            _ => Some(Speed::X1),
+        },
+    };
+    match clock.next_tick(speed, Instant::now()) {
+        true => ShouldRun::YesAndLoop,
+        false => ShouldRun::No,
+    }
+}
+
+/// Speed keys work only when no human is playing.
+pub fn controls(
+    mut clock: ResMut<Clock>,
+    runstate: Res<RunState>,
+    actions: Res<Actions>,
+) {
+    match runstate.gamestate.current().arena_mode() {
+        Some(mode) if !mode.has_player() => {},
+        _ => return,
+    }
+    if actions.just_pressed(Action::SpeedUp) {
+        clock.speed = clock.speed.faster();
+    }
+    if actions.just_pressed(Action::SlowDown) {
+        clock.speed = clock.speed.slower();
+    }
+    if actions.just_pressed(Action::Freeze) {
+        clock.frozen = !clock.frozen;
+    }
+    if actions.just_pressed(Action::Step) {
+        clock.frozen = true;
+        clock.step = true;
+    }
+}
+
+/// Replaces Rapier's own stepping, which happens once per frame.
+pub fn physics_step(
+    configuration: Res<RapierConfiguration>,
+    integration_parameters: Res<IntegrationParameters>,
+    mut pipeline: ResMut<PhysicsPipeline>,
+    mut broad_phase: ResMut<BroadPhase>,
+    mut narrow_phase: ResMut<NarrowPhase>,
+    mut bodies: ResMut<RigidBodySet>,
+    mut colliders: ResMut<ColliderSet>,
+    mut joints: ResMut<JointSet>,
+    events: Res<EventQueue>,
+    filter: Res<InteractionPairFilters>,
+) {
+    pipeline.step(
+        &configuration.gravity,
+        &integration_parameters,
+        &mut broad_phase,
+        &mut narrow_phase,
+        &mut bodies,
+        &mut colliders,
+        &mut joints,
+        filter.contact_filter.as_deref(),
+        filter.proximity_filter.as_deref(),
+        &*events,
+    );
+}
+
+/// Starts every benchmark round in an empty physics world,
+/// so that its result doesn't depend on the rounds before.
+/// Rapier remembers freed slots and pairs from earlier rounds otherwise.
+/// The previous round's bodies are gone by the time the next one is entered.
+pub fn reset_physics(
+    runstate: Res<RunState>,
+    mut pipeline: ResMut<PhysicsPipeline>,
+    mut broad_phase: ResMut<BroadPhase>,
+    mut narrow_phase: ResMut<NarrowPhase>,
+    mut bodies: ResMut<RigidBodySet>,
+    mut colliders: ResMut<ColliderSet>,
+    mut joints: ResMut<JointSet>,
+    mut events: ResMut<EventQueue>,
+    mut entity_maps: ResMut<EntityMaps>,
+) {
+    if runstate.gamestate.entering() != Some(&GameState::Arena(Mode::Benchmark)) {
+        return;
+    }
+    *pipeline = PhysicsPipeline::new();
+    *broad_phase = BroadPhase::new();
+    *narrow_phase = NarrowPhase::new();
+    *bodies = RigidBodySet::new();
+    *colliders = ColliderSet::new();
+    *joints = JointSet::new();
+    *events = EventQueue::new(true);
+    *entity_maps = EntityMaps::default();
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn count_ticks(clock: &mut Clock, speed: Option<Speed>) -> u32 {
+        let now = Instant::now();
+        let mut count = 0;
+        while clock.next_tick(speed, now) {
+            count += 1;
+        }
+        count
+    }
+
+    #[test]
+    fn ticks_per_frame() {
+        let mut clock = Clock::default();
+        assert_eq!(count_ticks(&mut clock, Some(Speed::X4)), 4);
+        assert_eq!(count_ticks(&mut clock, Some(Speed::X1)), 1);
+        assert_eq!(count_ticks(&mut clock, None), 0);
+        clock.step = true;
+        assert_eq!(count_ticks(&mut clock, None), 1);
+        assert_eq!(count_ticks(&mut clock, None), 0);
+    }
+
+    #[test]
+    fn speeds_stop_at_ends() {
+        assert_eq!(Speed::X1.slower(), Speed::X1);
+        assert_eq!(Speed::X16.faster(), Speed::Max);
+        assert_eq!(Speed::Max.faster(), Speed::Max);
+    }
+}
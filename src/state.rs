@@ -1,8 +1,19 @@
 use bevy::prelude::*;
 use core::fmt;
 use crate::util::PredicateContainer;
+use std::path::PathBuf;
 use super::arena::*;
+use super::benchmark::Benchmark;
+use super::curriculum::Curriculum;
+use super::leaderboard::Leaderboard;
+use super::scenario::Scenario;
+use super::coevolution;
+use super::fitness::Fitness;
+use super::geometry::Topology;
+use super::imitation;
 use super::mob::GenePool;
+use super::options::Options;
+use super::replay;
 use super::shooter;
 
 
@@ -30,6 +41,20 @@ impl<T: PartialEq> ForStates<T> {
 pub enum Mode {
     AI,
     Player,
+    /// A single genotype going through fixed scenarios
+    Benchmark,
+    /// The player together with AI shooters
+    Coop,
+}
+
+impl Mode {
+    /// A human is in the arena.
+    pub fn has_player(&self) -> bool {
+        match self {
+            Mode::Player | Mode::Coop => true,
+            Mode::AI | Mode::Benchmark => false,
+        }
+    }
 }
 
 pub type ValidStates = ForStates<GameState>;
@@ -39,13 +64,17 @@ pub enum GameState {
     /// Launch game state
     Begin,
     MainMenu,
+    /// Key bindings
+    Settings,
     Manager,
     Arena(Mode),
     ArenaPause(Mode),
     /// Round summary. Physics goes on, but the action is finished.
     ArenaOver(Mode),
-    /// Helper to clean up the arena
-    BetweenRounds,
+    /// Helper to clean up the arena. Next round will be in this mode.
+    BetweenRounds(Mode),
+    /// Playing back a recorded round
+    Replay,
 }
 
 impl Default for GameState {
@@ -83,20 +112,61 @@ pub struct RunState {
     pub gamestate: GameStateFsm<GameState>,
     pub player: Option<Entity>,
     pub arena: Option<Arena>,
-    pub score: Option<u32>,
+    /// Scores of borgs which died in this round.
+    pub fallen_scores: Vec<u32>,
+    /// Where the player's borg is in `fallen_scores`, once it died.
+    pub player_fallen: Option<usize>,
     pub mob_gene_pool: GenePool,
+    /// Mobs which played against humans breed separately.
+    pub human_mob_gene_pool: GenePool,
     pub shooter_gene_pool: shooter::GenePool,
+    /// Judges shooters for the gene pool
+    pub fitness: Box<dyn Fitness>,
+    pub coevolution: coevolution::History,
+    pub benchmark: Option<Benchmark>,
+    /// Where to save the shooter pool after changes
+    pub save_pool: Option<PathBuf>,
+    /// How mobs spawn in the arena
+    pub scenario: Scenario,
+    pub curriculum: Option<Curriculum>,
+    pub leaderboard: Leaderboard,
+    /// Name being typed in for the leaderboard
+    pub name_entry: Option<String>,
+    /// Records player rounds for imitation
+    pub recorder: Option<imitation::Recorder>,
+    /// Saves replays of all rounds
+    pub replays: Option<replay::Recorder>,
+    pub playback: Option<replay::Playback>,
+    /// How the arena edges connect
+    pub topology: Topology,
 }
 
 impl RunState {
-    pub fn new(start: GameState) -> RunState {
+    pub fn new(start: GameState, options: Options) -> RunState {
         RunState {
             gamestate: GameStateFsm::new(start),
             player: None,
             arena: None,
-            score: None,
+            fallen_scores: Vec::new(),
+            player_fallen: None,
             mob_gene_pool: GenePool::new_eden(),
-            shooter_gene_pool: shooter::GenePool::new_eden(),
+            human_mob_gene_pool: GenePool::new_eden(),
+            shooter_gene_pool: shooter::GenePool::new_eden(options.selection),
+            fitness: options.fitness,
+            coevolution: coevolution::History::new(),
+            benchmark: None,
+            save_pool: options.save_pool,
+            scenario: Scenario::default(),
+            curriculum: match options.curriculum {
+                true => Some(Curriculum::new()),
+                false => None,
+            },
+            leaderboard: Leaderboard::default(),
+            name_entry: None,
+            recorder: None,
+            replays: None,
+            playback: None,
+            topology: options.topology,
         }
     }
 }
@@ -154,6 +224,11 @@ impl<T: PartialEq + Eq + Copy + fmt::Debug + Default> GameStateFsm<T> {
         }
     }
 
+    /// True from `transit_to` until the next state is current.
+    pub fn in_transition(&self) -> bool {
+        self.next != Transition::None
+    }
+
     pub fn entering(&self) -> Option<&T> {
         match &self.next {
             Transition::Enter(next) => Some(next),
@@ -221,4 +296,19 @@ mod tests {
             &ForStates::<States>::from_func(|s| s == &States::AA)
         ));
     }
+
+    #[test]
+    pub fn in_transition_until_entered() {
+        let mut fsm = GameStateFsm::new(States::AA);
+        fsm.update();
+        fsm.update();
+        assert!(!fsm.in_transition());
+        fsm.transit_to(States::B);
+        assert!(fsm.in_transition());
+        fsm.update();
+        assert!(fsm.in_transition());
+        fsm.update();
+        assert!(!fsm.in_transition());
+        assert!(fsm.is(States::B));
+    }
 }
@@ -1,22 +1,94 @@
 use super::arena::START_LIFE;
 use super::buttons;
 use super::components::*;
+use super::gamepad;
+use super::input_map::{ Action, Actions, Binding, InputMap };
+use super::input_map;
+use super::leaderboard;
+use super::simulation;
 use super::state::{ GameState, Mode, RunState, ValidStates };
 use bevy::app::AppExit;
+use bevy::input::gamepad::{ GamepadButton, GamepadButtonType };
 use bevy::prelude::*;
 use bevy::prelude::{ ChildBuilder, Handle, Font };
 use bevy::ui::entity::{ ButtonBundle, ImageBundle, NodeBundle, TextBundle };
+use bevy::window::ReceivedCharacter;
+use std::path::Path;
 
 
 pub struct DrawBlinkTimer(pub Timer);
 
+/// High scores and the name being entered.
+pub struct UiLeaderboard;
 
+/// Key bindings on the settings screen.
+pub struct UiSettings;
+
+/// Position and speed of the replay.
+pub struct UiReplay;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum MenuAction {
     EnterShootMode,
     EnterAIMode,
+    EnterCoopMode,
+    OpenSettings,
     Quit,
 }
 
+impl MenuAction {
+    /// In the order of the buttons.
+    const ALL: [MenuAction; 5] = [
+        MenuAction::EnterShootMode,
+        MenuAction::EnterAIMode,
+        MenuAction::EnterCoopMode,
+        MenuAction::OpenSettings,
+        MenuAction::Quit,
+    ];
+
+    /// Triggers the menu entry from the keyboard.
+    fn action(&self) -> Action {
+        match self {
+            MenuAction::EnterShootMode => Action::StartPlayer,
+            MenuAction::EnterAIMode => Action::StartAI,
+            MenuAction::EnterCoopMode => Action::StartCoop,
+            MenuAction::OpenSettings => Action::Settings,
+            MenuAction::Quit => Action::Quit,
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            MenuAction::EnterShootMode => "Start shooting",
+            MenuAction::EnterAIMode => "AI mode",
+            MenuAction::EnterCoopMode => "Co-op",
+            MenuAction::OpenSettings => "Settings",
+            MenuAction::Quit => "Exit",
+        }
+    }
+
+    fn perform(&self, runstate: &mut RunState, app_exit_events: &mut Events<AppExit>) {
+        match self {
+            MenuAction::EnterShootMode => {
+                runstate.gamestate.transit_to(GameState::Arena(Mode::Player));
+            },
+            MenuAction::EnterAIMode => {
+                runstate.gamestate.transit_to(GameState::Arena(Mode::AI));
+            },
+            MenuAction::EnterCoopMode => {
+                runstate.gamestate.transit_to(GameState::Arena(Mode::Coop));
+            },
+            MenuAction::OpenSettings => {
+                runstate.gamestate.transit_to(GameState::Settings);
+            },
+            MenuAction::Quit => {
+                app_exit_events.send(AppExit);
+            },
+        }
+    }
+}
+
 
 fn add_text_button<'a, 'b, 'c, 'd>(
     commands: &'a mut ChildBuilder<'c>,
@@ -58,6 +130,7 @@ pub fn start_menu(
     asset_server: Res<AssetServer>,
     mut materials: ResMut<Assets<ColorMaterial>>,
     button_materials: Res<buttons::Materials>,
+    input_map: Res<InputMap>,
 ) {
     if let Some(GameState::MainMenu) = runstate.gamestate.entering() {
         let font_handle = asset_server.load("kenvector_future.ttf");
@@ -89,38 +162,54 @@ pub fn start_menu(
                         ..Default::default()
                     })
                     .with(ValidStates::from_func(|state| state == &GameState::MainMenu));
-                add_text_button(
-                    parent,
-                    &button_materials,
-                    &font_handle,
-                    "1: Start shooting".into(),
-                    MenuAction::EnterShootMode,
-                );
-                add_text_button(
-                    parent,
-                    &button_materials,
-                    &font_handle,
-                    "2: AI mode".into(),
-                    MenuAction::EnterAIMode,
-                );
-                add_text_button(
-                    parent,
-                    &button_materials,
-                    &font_handle,
-                    "Esc: Exit".into(),
-                    MenuAction::Quit,
-                );
+                for action in MenuAction::ALL.iter() {
+                    let key = input_map.bindings(action.action()).first()
+                        .map(Binding::name)
+                        .unwrap_or("-");
+                    add_text_button(
+                        parent,
+                        &button_materials,
+                        &font_handle,
+                        format!("{}: {}", key, action.label()),
+                        *action,
+                    );
+                }
             });
     }
 }
 
+/// How the player did compared to the shooters in the same arena.
+fn coop_summary(runstate: &RunState) -> String {
+    let player = runstate.player_fallen
+        .and_then(|i| runstate.fallen_scores.get(i))
+        .copied()
+        .unwrap_or(0);
+    let shooters: Vec<_> = runstate.fallen_scores.iter().enumerate()
+        .filter(|(i, _score)| Some(*i) != runstate.player_fallen)
+        .map(|(_i, score)| format!("{}", score))
+        .collect();
+    format!("You: {}\nShooters: {}", player, shooters.join(" "))
+}
+
 pub fn gameover_menu(
     commands: &mut Commands,
     runstate: ResMut<RunState>,
     asset_server: Res<AssetServer>,
     mut materials: ResMut<Assets<ColorMaterial>>,
 ) {
-    if let Some(GameState::ArenaOver(Mode::Player)) = runstate.gamestate.entering() {
+    if let Some(GameState::ArenaOver(mode)) = runstate.gamestate.entering() {
+        if !mode.has_player() {
+            return;
+        }
+        let mode = mode.clone();
+        let states = ValidStates::from_func(move |state|
+            state == &GameState::ArenaOver(mode)
+        );
+        // The leaderboard fills this in in player mode.
+        let summary = match mode {
+            Mode::Coop => coop_summary(&runstate),
+            _ => "".to_string(),
+        };
         let font_handle = asset_server.load("kenvector_future.ttf");
         commands
             .spawn(NodeBundle {
@@ -134,7 +223,7 @@ pub fn gameover_menu(
                 material: materials.add(Color::NONE.into()),
                 ..Default::default()
             })
-            .with(ValidStates::from_func(|state| state == &GameState::ArenaOver(Mode::Player)))
+            .with(states.clone())
             .with_children(|parent| {
                 parent
                     .spawn(TextBundle {
@@ -149,11 +238,11 @@ pub fn gameover_menu(
                         },
                         ..Default::default()
                     })
-                    .with(ValidStates::from_func(|state| state == &GameState::ArenaOver(Mode::Player)))
+                    .with(states.clone())
                     .spawn(TextBundle {
                         text: Text {
                             value: "enter".to_string(),
-                            font: font_handle,
+                            font: font_handle.clone(),
                             style: TextStyle {
                                 font_size: 50.0,
                                 color: Color::rgb_u8(0x44, 0x11, 0x11),
@@ -163,11 +252,228 @@ pub fn gameover_menu(
                         ..Default::default()
                     })
                     .with(DrawBlinkTimer(Timer::from_seconds(0.5, true)))
-                    .with(ValidStates::from_func(|state| state == &GameState::ArenaOver(Mode::Player)));
+                    .with(states.clone())
+                    .spawn(TextBundle {
+                        text: Text {
+                            value: summary,
+                            font: font_handle,
+                            style: TextStyle {
+                                font_size: 30.0,
+                                color: Color::rgb_u8(0x00, 0xAA, 0xAA),
+                                ..Default::default()
+                            },
+                        },
+                        ..Default::default()
+                    })
+                    .with(states.clone());
+                if mode == Mode::Player {
+                    parent.with(UiLeaderboard);
+                }
+            });
+    }
+}
+
+/// Asks for a name if the player's score made it to the leaderboard.
+pub fn leaderboard_input(
+    mut runstate: ResMut<RunState>,
+    mut reader: Local<EventReader<ReceivedCharacter>>,
+    characters: Res<Events<ReceivedCharacter>>,
+    input: Res<Input<KeyCode>>,
+    gamepads: Res<gamepad::Lobby>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    mut query: Query<Mut<Text>, With<UiLeaderboard>>,
+) {
+    let runstate = &mut *runstate;
+    if let Some(GameState::ArenaOver(Mode::Player)) = runstate.gamestate.entering() {
+        let score = runstate.fallen_scores.iter().sum();
+        if runstate.leaderboard.qualifies(score) {
+            runstate.name_entry = Some(String::new());
+        }
+        // Don't type in what was pressed before the round ended.
+        reader.iter(&characters).last();
+    }
+    if runstate.gamestate.current() != &GameState::ArenaOver(Mode::Player) {
+        return;
+    }
+
+    if let Some(name) = runstate.name_entry.as_mut() {
+        for c in reader.iter(&characters) {
+            if (c.char.is_alphanumeric() || c.char == ' ')
+                && name.chars().count() < leaderboard::MAX_NAME_LENGTH
+            {
+                name.push(c.char);
+            }
+        }
+        if input.just_pressed(KeyCode::Back) {
+            name.pop();
+        }
+        // Gamepads can't type, so they stay anonymous.
+        if input.just_pressed(KeyCode::Return)
+            || gamepads.just_pressed(&gamepad_buttons, GamepadButtonType::South)
+            || gamepads.just_pressed(&gamepad_buttons, GamepadButtonType::Start)
+        {
+            let name = match name.trim() {
+                "" => "anonymous".into(),
+                name => name.into(),
+            };
+            runstate.leaderboard.insert(leaderboard::Score {
+                name,
+                score: runstate.fallen_scores.iter().sum(),
+            });
+            if let Err(e) = runstate.leaderboard.save(Path::new(leaderboard::PATH)) {
+                eprintln!("Can't save the leaderboard: {}", e);
+            }
+            runstate.name_entry = None;
+        }
+    }
+
+    let value = match &runstate.name_entry {
+        Some(name) => format!("New high score! Name: {}_\n\n{}", name, runstate.leaderboard.table()),
+        None => runstate.leaderboard.table(),
+    };
+    for mut text in query.iter_mut() {
+        if text.value != value {
+            text.value = value.clone();
+        }
+    }
+}
+
+pub fn settings_menu(
+    commands: &mut Commands,
+    runstate: ResMut<RunState>,
+    asset_server: Res<AssetServer>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    if let Some(GameState::Settings) = runstate.gamestate.entering() {
+        let states = ValidStates::from_func(|state| state == &GameState::Settings);
+        let font_handle = asset_server.load("kenvector_future.ttf");
+        commands
+            .spawn(NodeBundle {
+                style: Style {
+                    size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    flex_direction: FlexDirection::ColumnReverse,
+                    ..Default::default()
+                },
+                material: materials.add(Color::NONE.into()),
+                ..Default::default()
+            })
+            .with(states.clone())
+            .with_children(|parent| {
+                parent
+                    .spawn(TextBundle {
+                        text: Text {
+                            value: "Settings".to_string(),
+                            font: font_handle.clone(),
+                            style: TextStyle {
+                                font_size: 50.0,
+                                color: Color::rgb_u8(0x00, 0xAA, 0xAA),
+                                ..Default::default()
+                            },
+                        },
+                        ..Default::default()
+                    })
+                    .with(states.clone())
+                    .spawn(TextBundle {
+                        text: Text {
+                            value: "".to_string(),
+                            font: font_handle,
+                            style: TextStyle {
+                                font_size: 20.0,
+                                color: Color::rgb_u8(0x00, 0xAA, 0xAA),
+                                ..Default::default()
+                            },
+                        },
+                        ..Default::default()
+                    })
+                    .with(states.clone())
+                    .with(UiSettings);
             });
     }
 }
 
+#[derive(Default)]
+pub struct SettingsState {
+    /// Index into `Action::ALL`
+    focused: usize,
+    /// Waiting for a key to bind
+    capturing: bool,
+}
+
+/// Up and down choose an action, Return waits for a key or mouse button to bind to it,
+/// Back restores the default, Escape saves and leaves.
+/// Those keys don't go through the bindings,
+/// so that bad bindings can always be undone.
+/// On the gamepad, the d-pad chooses, South binds, West restores,
+/// and East or Start leave, or cancel binding.
+pub fn settings(
+    mut runstate: ResMut<RunState>,
+    mut state: Local<SettingsState>,
+    mut input_map: ResMut<InputMap>,
+    keys: Res<Input<KeyCode>>,
+    mouse: Res<Input<MouseButton>>,
+    gamepads: Res<gamepad::Lobby>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    mut query: Query<Mut<Text>, With<UiSettings>>,
+) {
+    if runstate.gamestate.current() != &GameState::Settings {
+        return;
+    }
+    let pad = |kind| gamepads.just_pressed(&gamepad_buttons, kind);
+    let action = Action::ALL[state.focused];
+    if state.capturing {
+        if pad(GamepadButtonType::East) || pad(GamepadButtonType::Start) {
+            state.capturing = false;
+        }
+        let binding = keys.get_just_pressed()
+            .filter_map(|key| Binding::from_key(*key))
+            .chain(mouse.get_just_pressed().filter_map(|button| Binding::from_mouse(*button)))
+            .next();
+        if let Some(binding) = binding {
+            input_map.bind(action, vec![binding]);
+            state.capturing = false;
+        }
+    } else if keys.just_pressed(KeyCode::Down) || pad(GamepadButtonType::DPadDown) {
+        state.focused = (state.focused + 1) % Action::ALL.len();
+    } else if keys.just_pressed(KeyCode::Up) || pad(GamepadButtonType::DPadUp) {
+        state.focused = (state.focused + Action::ALL.len() - 1) % Action::ALL.len();
+    } else if keys.just_pressed(KeyCode::Return) || pad(GamepadButtonType::South) {
+        // The Return press itself gets skipped, because it's no longer new next frame.
+        state.capturing = true;
+    } else if keys.just_pressed(KeyCode::Back) || pad(GamepadButtonType::West) {
+        let default = InputMap::default().bindings(action).to_vec();
+        input_map.bind(action, default);
+    } else if keys.just_pressed(KeyCode::Escape)
+        || pad(GamepadButtonType::East)
+        || pad(GamepadButtonType::Start)
+    {
+        if let Err(e) = input_map.save(Path::new(input_map::PATH)) {
+            eprintln!("Can't save the key bindings: {}", e);
+        }
+        runstate.gamestate.transit_to(GameState::MainMenu);
+    }
+
+    let mut value = String::new();
+    for (i, line) in input_map.table().lines().enumerate() {
+        let marker = match (i == state.focused, state.capturing) {
+            (true, true) => "? ",
+            (true, false) => "> ",
+            (false, _) => "  ",
+        };
+        value.push_str(marker);
+        value.push_str(line);
+        value.push('\n');
+    }
+    value.push_str("\nUp/Down: choose, Enter: bind, Backspace: default, Esc: save and leave");
+    value.push_str("\nGamepad: d-pad, South, West, East");
+    for mut text in query.iter_mut() {
+        if text.value != value {
+            text.value = value.clone();
+        }
+    }
+}
+
 pub fn pause_menu(
     commands: &mut Commands,
     runstate: ResMut<RunState>,
@@ -213,6 +519,62 @@ pub fn pause_menu(
     }
 }
 
+pub fn replay_status(
+    commands: &mut Commands,
+    runstate: Res<RunState>,
+    asset_server: Res<AssetServer>,
+    mut query: Query<Mut<Text>, With<UiReplay>>,
+) {
+    if let Some(GameState::Replay) = runstate.gamestate.entering() {
+        commands
+            .spawn(TextBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: Rect {
+                        left: Val::Px(10.0),
+                        top: Val::Px(10.0),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+                text: Text {
+                    value: String::new(),
+                    font: asset_server.load("kenvector_future.ttf"),
+                    style: TextStyle {
+                        font_size: 30.0,
+                        color: Color::rgb_u8(0x00, 0xAA, 0xAA),
+                        ..Default::default()
+                    },
+                },
+                ..Default::default()
+            })
+            .with(ValidStates::from_func(|state| state == &GameState::Replay))
+            .with(UiReplay);
+        return;
+    }
+    if runstate.gamestate.current() != &GameState::Replay {
+        return;
+    }
+    if let Some(playback) = runstate.playback.as_ref() {
+        let value = format!(
+            "{:.1} / {:.1} {}{}",
+            playback.time(),
+            playback.duration(),
+            playback.speed.label(),
+            match playback.paused {
+                true => " paused",
+                false => "",
+            },
+        );
+        for mut text in query.iter_mut() {
+            // Avoid triggering change detection every frame
+            if text.value != value {
+                text.value = value.clone();
+            }
+        }
+    }
+}
+
 pub fn game_ui_spawn(
     commands: &mut Commands,
     runstate: ResMut<RunState>,
@@ -309,15 +671,39 @@ pub fn game_ui_spawn(
     }
 }
 
+/// Shows scores of living borgs first, then the fallen ones in parentheses.
 pub fn score(
-    runstate: ChangedRes<RunState>, 
+    runstate: Res<RunState>,
+    clock: Res<simulation::Clock>,
+    borgs: Query<&Borg>,
     mut query: Query<(Mut<Text>, &UiScore)>,
 ) {
     if !runstate.gamestate.current().is_arena() {
         return;
     }
+    let alive: Vec<_> = borgs.iter()
+        .map(|borg| format!("{}", borg.score))
+        .collect();
+    let fallen: Vec<_> = runstate.fallen_scores.iter()
+        .map(|score| format!("{}", score))
+        .collect();
+    let value = match fallen.is_empty() {
+        true => alive.join(" "),
+        false => format!("{} ({})", alive.join(" "), fallen.join(" ")),
+    };
+    let speed = match runstate.gamestate.current().arena_mode() {
+        Some(mode) if !mode.has_player() => clock.label(),
+        _ => String::new(),
+    };
+    let value = match speed.is_empty() {
+        true => value,
+        false => format!("[{}] {}", speed, value),
+    };
     for (mut text, _uiscore) in query.iter_mut() {
-        text.value = format!("{}", runstate.score.unwrap());
+        // Avoid triggering change detection every frame
+        if text.value != value {
+            text.value = value.clone();
+        }
     }
 }
 
@@ -348,39 +734,69 @@ pub fn button_click(
     >,
 ) {
     for (interaction, action) in interactions.iter_mut() {
-        match (interaction, action) {
-            (Interaction::Clicked, MenuAction::EnterShootMode) => {
-                runstate.gamestate.transit_to(GameState::Arena(Mode::Player));
-            },
-            (Interaction::Clicked, MenuAction::EnterAIMode) => {
-                runstate.gamestate.transit_to(GameState::Arena(Mode::AI));
-            },
-            (Interaction::Clicked, MenuAction::Quit) => {
-                app_exit_events.send(AppExit);
-            },
-            _ => {},
+        if let Interaction::Clicked = interaction {
+            action.perform(&mut runstate, &mut app_exit_events);
         }
     }
 }
 
 
+/// Bound keys choose menu entries directly.
+/// The d-pad moves between them, and the South button chooses.
 pub fn keyboard_menu(
     mut runstate: ResMut<RunState>,
-    input: Res<Input<KeyCode>>,
+    mut focused: Local<usize>,
+    actions: Res<Actions>,
+    gamepads: Res<gamepad::Lobby>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    button_materials: Res<buttons::Materials>,
     mut app_exit_events: ResMut<Events<AppExit>>,
+    mut buttons: Query<(Mut<Handle<ColorMaterial>>, &MenuAction), With<Button>>,
 ) {
     match runstate.gamestate.current().clone() {
         GameState::MainMenu => {
-            if input.just_pressed(KeyCode::Key1) {
-                runstate.gamestate.transit_to(GameState::Arena(Mode::Player));
+            for action in MenuAction::ALL.iter() {
+                if actions.just_pressed(action.action()) {
+                    action.perform(&mut runstate, &mut app_exit_events);
+                }
             }
-            if input.just_pressed(KeyCode::Key2) {
-                runstate.gamestate.transit_to(GameState::Arena(Mode::AI));
+
+            let count = MenuAction::ALL.len();
+            let moved = if gamepads.just_pressed(&gamepad_buttons, GamepadButtonType::DPadDown) {
+                *focused = (*focused + 1) % count;
+                true
+            } else if gamepads.just_pressed(&gamepad_buttons, GamepadButtonType::DPadUp) {
+                *focused = (*focused + count - 1) % count;
+                true
+            } else {
+                false
+            };
+            if moved {
+                for (mut material, action) in buttons.iter_mut() {
+                    *material = match *action == MenuAction::ALL[*focused] {
+                        true => button_materials.indicated.clone(),
+                        false => button_materials.normal.clone(),
+                    };
+                }
             }
-            if input.just_pressed(KeyCode::Escape) {
-                app_exit_events.send(AppExit);
+            if gamepads.just_pressed(&gamepad_buttons, GamepadButtonType::South) {
+                MenuAction::ALL[*focused].perform(&mut runstate, &mut app_exit_events);
             }
         },
         _ => {}
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::options::Options;
+
+    #[test]
+    fn coop_summary_ties() {
+        let mut runstate = RunState::new(GameState::Begin, Options::default());
+        runstate.fallen_scores = vec![50, 80, 50];
+        runstate.player_fallen = Some(2);
+        assert_eq!(coop_summary(&runstate), "You: 50\nShooters: 50 80");
+    }
+}
@@ -18,6 +18,7 @@ use bevy::ui::{ AlignItems, Node, PositionType, Style, Val };
 use bevy_prototype_lyon;
 use bevy_prototype_lyon::prelude::{ point, primitive, FillOptions, PathBuilder, ShapeType, StrokeOptions, TessellationMode };
 use crate::components::Borg;
+use crate::input_map::{ Action, Actions };
 use crate::shooter;
 use crate::shooter::BrainFed;
 use std::collections::HashMap;
@@ -33,11 +34,23 @@ pub struct Plugin;
 impl app::Plugin for Plugin {
     fn build(&self, app: &mut app::AppBuilder) {
         app.add_stage_after(app::stage::UPDATE, "draw_imm", SystemStage::parallel())
+            .add_resource(Shown::default())
+            .add_system(toggle.system())
             .add_system_to_stage("draw_imm", draw_preview.system())
             .add_system_to_stage(app::stage::UPDATE, kill_preview.system());
     }
 }
 
+/// The brain is drawn only after asking for it.
+#[derive(Default)]
+pub struct Shown(bool);
+
+fn toggle(actions: Res<Actions>, mut shown: ResMut<Shown>) {
+    if actions.just_pressed(Action::ToggleViewer) {
+        shown.0 = !shown.0;
+    }
+}
+
 
 struct Preview;
 
@@ -194,6 +207,7 @@ fn draw_brain(
 fn draw_preview(
     commands: &mut Commands,
     mut state: Local<FedEvents>,
+    shown: Res<Shown>,
     mut materials: ResMut<Assets<ColorMaterial>>,
     mut meshes: ResMut<Assets<Mesh>>,
     fed_events: Res<Events<BrainFed>>,
@@ -204,7 +218,10 @@ fn draw_preview(
         .and_then(|fed|
             brains.get(fed.entity).ok().map(|brain| (brain, &fed.inputs))
         );
-    
+    if !shown.0 {
+        return;
+    }
+
     if let Some((brain, inputs)) = brain_feed {
         commands
             .spawn(NodeBundle {
@@ -0,0 +1,102 @@
+/*! Kinds of weapons borgs can carry */
+
+/*
+ Author: Dorota Czaplejewicz <gihuac.dcz@porcupinefactory.org>
+ SPDX-License-Identifier: AGPL-3.0-or-later
+ */
+
+use rand::Rng;
+use serde::{ Deserialize, Serialize };
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum Kind {
+    /// A single shot
+    #[default]
+    Laser,
+    /// A fan of short-lived shots
+    Spread,
+    /// Goes through everything in its path
+    Beam,
+    /// Slow, and explodes on contact or at the end of its flight
+    Grenade,
+}
+
+/// What a weapon does when fired.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stats {
+    /// Seconds between shots
+    pub cooldown: f32,
+    /// Projectiles per shot
+    pub projectiles: u32,
+    /// Angle in radians over which projectiles are spread
+    pub spread: f32,
+    pub speed: f32,
+    /// Seconds before the projectile disappears
+    pub lifetime: f32,
+    /// Life points taken from each mob hit
+    pub damage: u32,
+    /// Projectile survives hits
+    pub piercing: bool,
+    /// Damages everything within this radius when it explodes
+    pub blast_radius: Option<f32>,
+    /// Projectile length relative to the laser
+    pub length: f32,
+}
+
+impl Kind {
+    pub const ALL: [Kind; 4] = [Kind::Laser, Kind::Spread, Kind::Beam, Kind::Grenade];
+
+    pub fn stats(&self) -> Stats {
+        match self {
+            Kind::Laser => Stats {
+                cooldown: 0.5,
+                projectiles: 1,
+                spread: 0.0,
+                speed: 200.0,
+                lifetime: 5.0,
+                damage: 1,
+                piercing: false,
+                blast_radius: None,
+                length: 1.0,
+            },
+            Kind::Spread => Stats {
+                cooldown: 0.9,
+                projectiles: 5,
+                spread: 0.8,
+                speed: 180.0,
+                lifetime: 1.0,
+                damage: 1,
+                piercing: false,
+                blast_radius: None,
+                length: 1.0,
+            },
+            Kind::Beam => Stats {
+                cooldown: 1.2,
+                projectiles: 1,
+                spread: 0.0,
+                speed: 400.0,
+                lifetime: 2.0,
+                damage: 1,
+                piercing: true,
+                blast_radius: None,
+                length: 4.0,
+            },
+            Kind::Grenade => Stats {
+                cooldown: 1.5,
+                projectiles: 1,
+                spread: 0.0,
+                speed: 80.0,
+                lifetime: 1.5,
+                damage: 2,
+                piercing: false,
+                blast_radius: Some(40.0),
+                length: 1.0,
+            },
+        }
+    }
+
+    pub fn random<R: Rng>(rng: &mut R) -> Kind {
+        Kind::ALL[rng.gen_range(0, Kind::ALL.len())]
+    }
+}